@@ -6,6 +6,8 @@ use regex::Regex;
 
 use std::collections::VecDeque;
 
+use adventofcode_rust::summed_area::SummedAreaTable;
+
 struct Claim {
     id: u32,
     x: u32,
@@ -14,21 +16,8 @@ struct Claim {
     h: u32,
 }
 
-fn number_of_claims(x: u32, y: u32, claims: &VecDeque<Claim>) -> u32 {
-    let mut count = 0;
-    for claim in claims.iter() {
-        if y >= claim.y && y < claim.y + claim.h {
-            if x >= claim.x && x < claim.x + claim.w {
-                count += 1;
-            }
-        }
-    }
-    count
-}
-
 fn main() {
     let mut claims = VecDeque::new();
-    let mut cloth = [[0u32; 1000]; 1000];
 
     let re = Regex::new(r"^#(\d+) @ (\d+),(\d+): (\d+)x(\d+)$").unwrap();
     for line in io::stdin().lock().lines() {
@@ -43,35 +32,34 @@ fn main() {
         }
     }
 
+    let width = claims.iter().map(|c| c.x + c.w).max().unwrap_or(0) as usize;
+    let height = claims.iter().map(|c| c.y + c.h).max().unwrap_or(0) as usize;
+
+    // Mark each claim by its corners, so that the prefix sum at a square is
+    // the number of claims on it.
+    let mut corners = vec![vec![0i32; width + 1]; height + 1];
     for claim in claims.iter() {
-        for y in claim.y..(claim.y + claim.h) {
-            for x in claim.x..(claim.x + claim.w) {
-                cloth[y as usize][x as usize] += 1;
-            }
-        }
+        let (x0, y0) = (claim.x as usize, claim.y as usize);
+        let (x1, y1) = (x0 + claim.w as usize, y0 + claim.h as usize);
+        corners[y0][x0] += 1;
+        corners[y0][x1] -= 1;
+        corners[y1][x0] -= 1;
+        corners[y1][x1] += 1;
     }
+    let claim_counts = SummedAreaTable::from_grid(&corners);
 
-    let mut count: u32 = 0;
-    for y in 0..1000 {
-        for x in 0..1000 {
-            if cloth[y as usize][x as usize] > 1 {
-                count += 1;
-            }
-        }
-    }
+    let overlaps = SummedAreaTable::new(width, height, |x, y| {
+        if claim_counts.prefix_sum(x, y) > 1 { 1 } else { 0 }
+    });
 
+    let count = overlaps.sum(0, 0, width, height);
     println!("Number of squares with more than one claim: {}", count);
 
-    'claim_check: for claim in claims.iter() {
-        for y in claim.y..(claim.y + claim.h) {
-            for x in claim.x..(claim.x + claim.w) {
-                if number_of_claims(x, y, &claims) > 1 {
-                    continue 'claim_check;
-                }
-            }
+    for claim in claims.iter() {
+        let (x, y, w, h) = (claim.x as usize, claim.y as usize, claim.w as usize, claim.h as usize);
+        if overlaps.sum(x, y, w, h) == 0 {
+            println!("ID of claim with no overlapping other claims: {}", claim.id);
+            break;
         }
-
-        println!("ID of claim with no overlapping other claims: {}", claim.id);
-        break;
     }
 }
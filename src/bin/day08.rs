@@ -1,88 +1,179 @@
 use std::io;
 use std::io::prelude::*;
 
+use std::fmt;
+
 struct Node {
-    metadata: Vec<u8>,
-    children: Vec<Node>,
+    metadata: Vec<u32>,
+    // Indices into the tree's nodes, always after the node's own index.
+    children: Vec<usize>,
+}
+
+// The nodes in the order they appear in the license, so the root comes
+// first and every node comes before its children.
+struct Tree {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug, PartialEq)]
+enum ParseErrorKind {
+    InvalidEntry(String),
+    // The license ended in the middle of a node.
+    Truncated,
+    // The root node ended before the license did, with this many entries left.
+    TrailingData(usize),
 }
 
-impl Node {
-    fn new() -> Node {
-        Node {
-            metadata: Vec::new(),
-            children: Vec::new(),
+#[derive(Debug, PartialEq)]
+struct ParseError {
+    entry: usize,
+    kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.kind {
+            ParseErrorKind::InvalidEntry(text) => write!(f, "invalid entry {:?}", text)?,
+            ParseErrorKind::Truncated => write!(f, "license ends in the middle of a node")?,
+            ParseErrorKind::TrailingData(n) => write!(f, "{} entries after the root node", n)?,
         }
+        write!(f, " at entry {}", self.entry)
     }
+}
 
-    fn parse(&mut self, mut input: &[u8]) -> usize {
-        let num_children = input[0];
-        let num_metadata = input[1] as usize;
-
-        input = &input[2..];
-        let mut num_read_total: usize = num_metadata + 2;
+fn parse_entries(input: &str) -> Result<Vec<u32>, ParseError> {
+    input.split_whitespace().enumerate().map(|(entry, text)| {
+        text.parse().map_err(|_| ParseError { entry, kind: ParseErrorKind::InvalidEntry(text.to_string()) })
+    }).collect()
+}
 
-        for _i in 0..num_children {
-            let mut child = Node::new();
-            let num_read = child.parse(input);
-            self.children.push(child);
+impl Tree {
+    // Reads the license with a stack of the nodes that still have children
+    // to come, so that deep trees don't run out of call stack.
+    fn parse(input: &[u32]) -> Result<Tree, ParseError> {
+        let mut entries = input.iter().copied().enumerate();
+        let mut next = || entries.next().ok_or(ParseError { entry: input.len(), kind: ParseErrorKind::Truncated });
+
+        let mut nodes: Vec<Node> = Vec::new();
+        // Each open node with the number of children left to read, and the
+        // number of metadata entries that come after them.
+        let mut stack: Vec<(usize, u32, u32)> = Vec::new();
+
+        loop {
+            let (_, num_children) = next()?;
+            let (_, num_metadata) = next()?;
+            let index = nodes.len();
+            nodes.push(Node { metadata: Vec::new(), children: Vec::new() });
+            if let Some(parent) = stack.last_mut() {
+                nodes[parent.0].children.push(index);
+                parent.1 -= 1;
+            }
+            stack.push((index, num_children, num_metadata));
 
-            num_read_total += num_read;
-            input = &input[num_read..];
+            // Close every node that has all its children, innermost first.
+            while let Some(&(index, 0, num_metadata)) = stack.last() {
+                for _i in 0..num_metadata {
+                    nodes[index].metadata.push(next()?.1);
+                }
+                stack.pop();
+            }
+            if stack.is_empty() {
+                break;
+            }
         }
 
-        for i in 0..num_metadata {
-            self.metadata.push(input[i as usize]);
+        let read = nodes.iter().map(|n| 2 + n.metadata.len()).sum::<usize>();
+        if read < input.len() {
+            return Err(ParseError { entry: read, kind: ParseErrorKind::TrailingData(input.len() - read) });
         }
-
-        num_read_total
+        Ok(Tree { nodes })
     }
 
-    fn sum_of_metadata(&self) -> u32 {
-        let mut sum = 0;
+    fn sum_of_metadata(&self) -> u64 {
+        self.nodes.iter().flat_map(|n| n.metadata.iter()).map(|&m| m as u64).sum()
+    }
 
-        for child in self.children.iter() {
-            sum += child.sum_of_metadata();
+    // Works from the last node back, so that the values of a node's
+    // children are known by the time it comes up.
+    fn value(&self) -> u64 {
+        let mut values = vec![0u64; self.nodes.len()];
+        for (i, node) in self.nodes.iter().enumerate().rev() {
+            values[i] = if node.children.is_empty() {
+                node.metadata.iter().map(|&m| m as u64).sum()
+            } else {
+                node.metadata.iter()
+                    .filter_map(|&m| (m as usize).checked_sub(1).and_then(|c| node.children.get(c)))
+                    .map(|&child| values[child])
+                    .sum()
+            };
         }
+        values.first().copied().unwrap_or(0)
+    }
+}
 
-        for i in 0..self.metadata.len() {
-            sum += self.metadata[i] as u32;
+fn main() -> io::Result<()> {
+    let mut input = String::new();
+    io::stdin().lock().read_to_string(&mut input)?;
+
+    let root = match parse_entries(&input).and_then(|entries| Tree::parse(&entries)) {
+        Ok(tree) => tree,
+        Err(e) => {
+            eprintln!("Invalid license: {}", e);
+            std::process::exit(1);
         }
+    };
 
-        sum
-    }
+    println!("The sum of all metadata: {}", root.sum_of_metadata());
+    println!("The value of the root node: {}", root.value());
 
-    fn value(&self) -> u32 {
-        if self.children.len() == 0 {
-            self.sum_of_metadata()
-        } else {
-            let mut value = 0;
+    Ok(())
+}
 
-            for i in 0..self.metadata.len() {
-                let index = self.metadata[i] as usize;
-                if let Some(child) = self.children.get(index - 1) {
-                    value += child.value();
-                }
-            }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            value
-        }
+    fn sum_and_value(entries: &[u32]) -> (u64, u64) {
+        let tree = Tree::parse(entries).unwrap();
+        (tree.sum_of_metadata(), tree.value())
     }
-}
 
-fn main() -> io::Result<()> {
-    let mut input: Vec<u8> = Vec::new();
+    #[test]
+    fn example() {
+        let entries = parse_entries("2 3 0 3 10 11 12 1 1 0 1 99 2 1 1 2").unwrap();
+        assert_eq!(sum_and_value(&entries), (138, 66));
+    }
 
-    for line in io::stdin().lock().lines() {
-        for entry in line.unwrap().split(' ') {
-            input.push(entry.parse().unwrap());
-        }
+    #[test]
+    fn entries_too_large_for_a_byte() {
+        assert_eq!(sum_and_value(&[1, 2, 0, 1, 300, 0, 1]), (301, 300));
     }
 
-    let mut root = Node::new();
-    root.parse(&input[..]);
+    #[test]
+    fn node_without_metadata() {
+        assert_eq!(sum_and_value(&[0, 0]), (0, 0));
+    }
 
-    println!("The sum of all metadata: {}", root.sum_of_metadata());
-    println!("The value of the root node: {}", root.value());
+    #[test]
+    fn very_deep_tree() {
+        let depth = 1_000_000;
+        let mut deep = Vec::new();
+        for _i in 0..depth {
+            deep.extend_from_slice(&[1, 1]);
+        }
+        deep.extend_from_slice(&[0, 1, 7]);
+        deep.resize(deep.len() + depth, 1);
+        assert_eq!(sum_and_value(&deep), (7 + depth as u64, 7));
+    }
 
-    Ok(())
+    #[test]
+    fn broken_licenses() {
+        let truncated = |entry| Err(ParseError { entry, kind: ParseErrorKind::Truncated });
+        assert_eq!(Tree::parse(&[2, 3, 0, 3, 10]).map(|t| t.nodes.len()), truncated(5));
+        assert_eq!(Tree::parse(&[1]).map(|t| t.nodes.len()), truncated(1));
+        assert_eq!(
+            Tree::parse(&[0, 1, 5, 7, 8]).map(|t| t.nodes.len()),
+            Err(ParseError { entry: 3, kind: ParseErrorKind::TrailingData(2) })
+        );
+    }
 }
@@ -1,74 +1,429 @@
 use std::io;
 use std::io::prelude::*;
 
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+
 extern crate regex;
 use regex::Regex;
 
+// The rules of the game: every `special_modulus`-th marble is kept by the
+// player along with the marble `counterclockwise_steps` counterclockwise of
+// the current one, and every other marble goes in after the marble
+// `clockwise_steps` clockwise of the current one.
+#[derive(Clone, Copy, Debug)]
+struct Rules {
+    special_modulus: u32,
+    clockwise_steps: usize,
+    counterclockwise_steps: usize,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        Rules {
+            special_modulus: 23,
+            clockwise_steps: 1,
+            counterclockwise_steps: 7,
+        }
+    }
+}
+
+// A circle of marbles with a current marble.
+trait MarbleCircle {
+    fn with_capacity(capacity: usize) -> Self;
+    fn clockwise(&mut self, n: usize);
+    fn counterclockwise(&mut self, n: usize);
+    // Puts a marble clockwise of the current one, and makes it current.
+    fn insert(&mut self, value: u32);
+    // Takes the current marble out, and makes the marble clockwise of it current.
+    fn remove(&mut self) -> u32;
+    fn current(&self) -> u32;
+    // All marbles in clockwise order, starting from the lowest one.
+    fn marbles(&self) -> Vec<u32>;
+}
+
 struct Marble {
     value: u32,
     next: usize,
     prev: usize,
 }
 
-struct MarbleCircle {
+// A doubly linked list in a Vec. Removed marbles stay in the Vec, unlinked.
+struct LinkedCircle {
     circle: Vec<Marble>,
     current_marble: usize,
+    len: usize,
 }
 
-impl MarbleCircle {
-    fn new() -> MarbleCircle {
-        let mut v = Vec::with_capacity(8388608);
+impl MarbleCircle for LinkedCircle {
+    fn with_capacity(capacity: usize) -> LinkedCircle {
+        let mut v = Vec::with_capacity(capacity);
         v.push(Marble {
             value: 0,
             next: 0,
             prev: 0,
         });
-        MarbleCircle {
+        LinkedCircle {
             circle: v,
             current_marble: 0,
+            len: 1,
         }
     }
 
-    fn clockwise(&mut self, n: u8) {
-        for _i in 0..n {
+    fn clockwise(&mut self, n: usize) {
+        for _i in 0..n % self.len {
             self.current_marble = self.circle[self.current_marble].next;
         }
     }
 
-    fn counterclockwise(&mut self, n: u8) {
-        for _i in 0..n {
+    fn counterclockwise(&mut self, n: usize) {
+        for _i in 0..n % self.len {
             self.current_marble = self.circle[self.current_marble].prev;
         }
     }
 
-    fn insert_after_current(&mut self, value: u32) {
+    fn insert(&mut self, value: u32) {
         let next_marble: usize = self.circle[self.current_marble].next;
         let new_marble: usize = self.circle.len();
 
         self.circle[self.current_marble].next = new_marble;
         self.circle[next_marble].prev = new_marble;
 
-        self.circle.push({
-            Marble {
-                value,
-                prev: self.current_marble,
-                next: next_marble,
-            }
-        })
+        self.circle.push(Marble {
+            value,
+            prev: self.current_marble,
+            next: next_marble,
+        });
+        self.current_marble = new_marble;
+        self.len += 1;
     }
 
-    fn remove_before_current(&mut self) -> u32 {
-        let marble_to_remove: usize = self.circle[self.current_marble].prev;
-        let new_prev_marble: usize = self.circle[marble_to_remove].prev;
+    fn remove(&mut self) -> u32 {
+        let marble_to_remove: usize = self.current_marble;
+        let prev_marble: usize = self.circle[marble_to_remove].prev;
+        let next_marble: usize = self.circle[marble_to_remove].next;
 
-        self.circle[self.current_marble].prev = new_prev_marble;
-        self.circle[new_prev_marble].next = self.current_marble;
+        self.circle[prev_marble].next = next_marble;
+        self.circle[next_marble].prev = prev_marble;
 
+        self.current_marble = next_marble;
+        self.len -= 1;
         self.circle[marble_to_remove].value
     }
+
+    fn current(&self) -> u32 {
+        self.circle[self.current_marble].value
+    }
+
+    fn marbles(&self) -> Vec<u32> {
+        let mut marble = self.current_marble;
+        let mut lowest = marble;
+        for _i in 0..self.len {
+            if self.circle[marble].value < self.circle[lowest].value {
+                lowest = marble;
+            }
+            marble = self.circle[marble].next;
+        }
+
+        let mut marbles = Vec::with_capacity(self.len);
+        marble = lowest;
+        for _i in 0..self.len {
+            marbles.push(self.circle[marble].value);
+            marble = self.circle[marble].next;
+        }
+        marbles
+    }
+}
+
+// The circle in a VecDeque, rotated so that the current marble is at the back.
+struct DequeCircle {
+    circle: VecDeque<u32>,
+}
+
+impl MarbleCircle for DequeCircle {
+    fn with_capacity(capacity: usize) -> DequeCircle {
+        let mut circle = VecDeque::with_capacity(capacity);
+        circle.push_back(0);
+        DequeCircle { circle }
+    }
+
+    fn clockwise(&mut self, n: usize) {
+        let n = n % self.circle.len();
+        self.circle.rotate_left(n);
+    }
+
+    fn counterclockwise(&mut self, n: usize) {
+        let n = n % self.circle.len();
+        self.circle.rotate_right(n);
+    }
+
+    fn insert(&mut self, value: u32) {
+        self.circle.push_back(value);
+    }
+
+    fn remove(&mut self) -> u32 {
+        let value = self.circle.pop_back().unwrap();
+        if !self.circle.is_empty() {
+            self.circle.rotate_left(1);
+        }
+        value
+    }
+
+    fn current(&self) -> u32 {
+        *self.circle.back().unwrap()
+    }
+
+    fn marbles(&self) -> Vec<u32> {
+        let lowest = (0..self.circle.len()).min_by_key(|&i| self.circle[i]).unwrap_or(0);
+        let mut marbles: Vec<u32> = self.circle.iter().copied().collect();
+        marbles.rotate_left(lowest);
+        marbles
+    }
+}
+
+// A turn on which a player kept marbles.
+#[derive(Debug)]
+struct ScoringTurn {
+    marble: u32,
+    player: usize,
+    removed: u32,
+    score: u64,
+}
+
+// The circle after a turn, with the player who played it. Turn 0 is the
+// circle before anyone has played.
+#[derive(Debug)]
+struct Snapshot {
+    player: Option<usize>,
+    marbles: Vec<u32>,
+    current: u32,
+}
+
+// Written out the way the puzzle shows the example game.
+impl fmt::Display for Snapshot {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut line = match self.player {
+            Some(player) => format!("[{}] ", player),
+            None => String::from("[-] "),
+        };
+        for &marble in self.marbles.iter() {
+            if marble == self.current {
+                let text = format!("({})", marble);
+                for _i in 3..text.len() {
+                    if line.ends_with(' ') {
+                        line.pop();
+                    }
+                }
+                line.push_str(&text);
+            } else {
+                line.push_str(&format!("{:>2} ", marble));
+            }
+        }
+        write!(f, "{}", line)
+    }
+}
+
+#[derive(Debug, Default)]
+struct History {
+    scoring_turns: Vec<ScoringTurn>,
+    snapshot_turns: BTreeSet<u32>,
+    snapshots: Vec<Snapshot>,
+}
+
+impl History {
+    // Records every scoring turn, and the circle after each of `snapshot_turns`.
+    fn new(snapshot_turns: impl IntoIterator<Item = u32>) -> History {
+        History {
+            snapshot_turns: snapshot_turns.into_iter().collect(),
+            ..History::default()
+        }
+    }
+
+    // Each score the player has had, with the marble that got them there.
+    fn score_over_time(&self, player: usize) -> Vec<(u32, u64)> {
+        let mut total = 0;
+        let mut scores = Vec::new();
+        for turn in self.scoring_turns.iter().filter(|t| t.player == player) {
+            total += turn.score;
+            scores.push((turn.marble, total));
+        }
+        scores
+    }
+}
+
+struct Game<C> {
+    circle: C,
+    rules: Rules,
+    scores: Vec<u64>,
+    last_marble: u32,
+    history: Option<History>,
+}
+
+impl<C: MarbleCircle> Game<C> {
+    fn new(num_players: usize, rules: Rules, capacity: usize) -> Game<C> {
+        Game {
+            circle: C::with_capacity(capacity),
+            rules,
+            scores: vec![0; num_players],
+            last_marble: 0,
+            history: None,
+        }
+    }
+
+    // Starts keeping a history of the game from the next turn on.
+    fn record(&mut self, history: History) {
+        self.history = Some(history);
+        self.snapshot(None);
+    }
+
+    // Players are numbered from 1.
+    fn player(&self, marble: u32) -> usize {
+        (marble as usize - 1) % self.scores.len() + 1
+    }
+
+    fn snapshot(&mut self, player: Option<usize>) {
+        let turn = self.last_marble;
+        if let Some(history) = self.history.as_mut() {
+            if history.snapshot_turns.contains(&turn) {
+                history.snapshots.push(Snapshot {
+                    player,
+                    marbles: self.circle.marbles(),
+                    current: self.circle.current(),
+                });
+            }
+        }
+    }
+
+    // Plays the next marble, and returns what it scored.
+    fn play_turn(&mut self) -> u64 {
+        let marble = self.last_marble + 1;
+        self.last_marble = marble;
+
+        let player = self.player(marble);
+
+        let mut score = 0;
+        if marble % self.rules.special_modulus != 0 {
+            self.circle.clockwise(self.rules.clockwise_steps);
+            self.circle.insert(marble);
+        } else {
+            self.circle.counterclockwise(self.rules.counterclockwise_steps);
+            let removed = self.circle.remove();
+            score = marble as u64 + removed as u64;
+            self.scores[player - 1] += score;
+            if let Some(history) = self.history.as_mut() {
+                history.scoring_turns.push(ScoringTurn { marble, player, removed, score });
+            }
+        }
+
+        self.snapshot(Some(player));
+        score
+    }
+
+    // Carries on playing until `last_marble` has been played.
+    fn play_until(&mut self, last_marble: u32) {
+        while self.last_marble < last_marble {
+            self.play_turn();
+        }
+    }
+
+    fn high_score(&self) -> u64 {
+        self.scores.iter().copied().max().unwrap_or(0)
+    }
+
+    // The player with the highest score, and the score. Ties go to the
+    // lowest numbered player.
+    fn winner(&self) -> Option<(usize, u64)> {
+        let mut winner: Option<(usize, u64)> = None;
+        for (i, &score) in self.scores.iter().enumerate() {
+            match winner {
+                Some((_, best)) if best >= score => {}
+                _ => winner = Some((i + 1, score)),
+            }
+        }
+        winner
+    }
+}
+
+fn record_game<C: MarbleCircle>(
+    num_players: usize,
+    last_marble: u32,
+    rules: Rules,
+    snapshot_turns: impl IntoIterator<Item = u32>,
+) -> (History, Option<(usize, u64)>) {
+    let mut game: Game<C> = Game::new(num_players, rules, last_marble as usize + 1);
+    game.record(History::new(snapshot_turns));
+    game.play_until(last_marble);
+    let winner = game.winner();
+    (game.history.unwrap(), winner)
+}
+
+// Prints the circle after every turn up to `last_marble`, like the puzzle's
+// example, followed by how the winner got their score.
+fn replay<C: MarbleCircle>(num_players: usize, last_marble: u32, rules: Rules) {
+    let (history, winner) = record_game::<C>(num_players, last_marble, rules, 0..=last_marble);
+    for snapshot in history.snapshots.iter() {
+        println!("{}", snapshot);
+    }
+    if let Some((player, score)) = winner {
+        println!("Player {} wins with {} points", player, score);
+        let turns = history.scoring_turns.iter().filter(|t| t.player == player);
+        for (turn, (_, total)) in turns.zip(history.score_over_time(player)) {
+            println!("  marble {} took marble {}: {} points", turn.marble, turn.removed, total);
+        }
+    }
+}
+
+fn play<C: MarbleCircle>(num_players: usize, max_marble: u32, rules: Rules) {
+    let mut game: Game<C> = Game::new(num_players, rules, max_marble as usize * 100 + 1);
+
+    game.play_until(max_marble);
+    println!("The player with the best score scored: {}", game.high_score());
+    if let Some((player, score)) = game.winner() {
+        println!("Winner: player {} with {} points", player, score);
+    }
+
+    game.play_until(max_marble * 100);
+    println!("If the max marble were 100 times as large: {}", game.high_score());
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: day09 [--backend list|deque] [--modulus <N>] [--clockwise <STEPS>] [--counterclockwise <STEPS>] [--replay <LAST_MARBLE>]");
+    std::process::exit(1);
 }
 
 fn main() -> io::Result<()> {
+    let mut rules = Rules::default();
+    let mut deque = false;
+    let mut replay_until = None;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--backend" => match args.next().as_deref() {
+                Some("list") => deque = false,
+                Some("deque") => deque = true,
+                _ => usage(),
+            },
+            "--modulus" => match args.next().and_then(|v| v.parse().ok()) {
+                Some(n) if n >= 2 => rules.special_modulus = n,
+                _ => usage(),
+            },
+            "--clockwise" => match args.next().and_then(|v| v.parse().ok()) {
+                Some(n) => rules.clockwise_steps = n,
+                None => usage(),
+            },
+            "--counterclockwise" => match args.next().and_then(|v| v.parse().ok()) {
+                Some(n) => rules.counterclockwise_steps = n,
+                None => usage(),
+            },
+            "--replay" => match args.next().and_then(|v| v.parse().ok()) {
+                Some(n) => replay_until = Some(n),
+                None => usage(),
+            },
+            _ => usage(),
+        }
+    }
+
     let mut num_players: usize = 0;
     let mut max_marble: u32 = 0;
 
@@ -79,40 +434,86 @@ fn main() -> io::Result<()> {
             max_marble = caps[2].parse().unwrap();
         }
     }
-    let mut scores: Vec<u32> = Vec::with_capacity(num_players);
-    for _i in 0..num_players {
-        scores.push(0u32);
+
+    if let Some(last_marble) = replay_until {
+        if deque {
+            replay::<DequeCircle>(num_players, last_marble, rules);
+        } else {
+            replay::<LinkedCircle>(num_players, last_marble, rules);
+        }
+        return Ok(());
+    }
+
+    if deque {
+        play::<DequeCircle>(num_players, max_marble, rules);
+    } else {
+        play::<LinkedCircle>(num_players, max_marble, rules);
     }
 
-    let mut circle = MarbleCircle::new();
+    Ok(())
+}
 
-    for next_marble in 1..=max_marble * 100 {
-        if next_marble == max_marble {
-            println!(
-                "The player with the best score scored: {}",
-                scores.iter().max().unwrap()
-            );
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-        let mut score: u32 = 0;
+    // The example games from the puzzle: players, last marble, high score.
+    const EXAMPLES: [(usize, u32, u64); 6] = [
+        (9, 25, 32),
+        (10, 1618, 8317),
+        (13, 7999, 146373),
+        (17, 1104, 2764),
+        (21, 6111, 54718),
+        (30, 5807, 37305),
+    ];
 
-        if next_marble % 23 == 0 {
-            score += next_marble;
-            circle.counterclockwise(6);
-            score += circle.remove_before_current();
-        } else {
-            circle.clockwise(1);
-            circle.insert_after_current(next_marble);
-            circle.clockwise(1);
+    fn high_score<C: MarbleCircle>(num_players: usize, last_marble: u32) -> u64 {
+        let mut game: Game<C> = Game::new(num_players, Rules::default(), last_marble as usize + 1);
+        game.play_until(last_marble);
+        game.high_score()
+    }
+
+    #[test]
+    fn example_games_with_linked_circle() {
+        for &(num_players, last_marble, expected) in EXAMPLES.iter() {
+            assert_eq!(high_score::<LinkedCircle>(num_players, last_marble), expected);
         }
+    }
 
-        scores[(next_marble as usize % num_players) as usize] += score;
+    #[test]
+    fn example_games_with_deque_circle() {
+        for &(num_players, last_marble, expected) in EXAMPLES.iter() {
+            assert_eq!(high_score::<DequeCircle>(num_players, last_marble), expected);
+        }
     }
 
-    println!(
-        "If the max marble were 100 times as large: {}",
-        scores.iter().max().unwrap()
-    );
+    // Lines of the first example game as the puzzle shows them.
+    const EXAMPLE_LINES: [(u32, &str); 6] = [
+        (0, "[-] (0)"),
+        (1, "[1]  0 (1)"),
+        (4, "[4]  0 (4) 2  1  3 "),
+        (22, "[4]  0 16  8 17  4 18  9 19  2 20 10 21  5(22)11  1 12  6 13  3 14  7 15 "),
+        (23, "[5]  0 16  8 17  4 18(19) 2 20 10 21  5 22 11  1 12  6 13  3 14  7 15 "),
+        (25, "[7]  0 16  8 17  4 18 19  2 24 20(25)10 21  5 22 11  1 12  6 13  3 14  7 15 "),
+    ];
 
-    Ok(())
+    fn check_replay<C: MarbleCircle>() {
+        let snapshot_turns = EXAMPLE_LINES.iter().map(|&(turn, _)| turn);
+        let (history, winner) = record_game::<C>(9, 25, Rules::default(), snapshot_turns);
+        let lines: Vec<String> = history.snapshots.iter().map(|s| s.to_string()).collect();
+        let expected: Vec<&str> = EXAMPLE_LINES.iter().map(|&(_, line)| line).collect();
+        assert_eq!(lines, expected);
+        assert_eq!(winner, Some((5, 32)));
+        assert_eq!(history.score_over_time(5), vec![(23, 32)]);
+    }
+
+    #[test]
+    fn example_replay_with_linked_circle() {
+        check_replay::<LinkedCircle>();
+    }
+
+    #[test]
+    fn example_replay_with_deque_circle() {
+        check_replay::<DequeCircle>();
+    }
 }
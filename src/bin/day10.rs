@@ -5,6 +5,11 @@ use std::io;
 use std::io::prelude::*;
 
 use std::cmp::{max, min};
+use std::fs::File;
+use std::io::BufWriter;
+
+use adventofcode_rust::netpbm;
+use adventofcode_rust::ocr;
 
 struct Point {
     x: i64,
@@ -21,43 +26,281 @@ impl Point {
         let vy = caps[4].parse().unwrap();
         Point { x, y, vx, vy }
     }
+
+    fn position_at(&self, t: i64) -> (i64, i64) {
+        (self.x + self.vx * t, self.y + self.vy * t)
+    }
+}
+
+fn positions_at(points: &[Point], t: i64) -> Vec<(i64, i64)> {
+    points.iter().map(|p| p.position_at(t)).collect()
+}
+
+// The smallest and largest x and y of the positions.
+fn bounding_box(positions: impl Iterator<Item = (i64, i64)>) -> (i64, i64, i64, i64) {
+    let mut min_x = i64::MAX;
+    let mut max_x = i64::MIN;
+    let mut min_y = i64::MAX;
+    let mut max_y = i64::MIN;
+
+    for (x, y) in positions {
+        min_x = min(min_x, x);
+        min_y = min(min_y, y);
+        max_x = max(max_x, x);
+        max_y = max(max_y, y);
+    }
+    (min_x, min_y, max_x, max_y)
+}
+
+fn area_at(points: &[Point], t: i64) -> i128 {
+    let (min_x, min_y, max_x, max_y) = bounding_box(points.iter().map(|p| p.position_at(t)));
+    (max_x - min_x + 1) as i128 * (max_y - min_y + 1) as i128
+}
+
+// Steps time from `start` one second at a time, forwards or backwards,
+// for as long as the bounding box of the points keeps getting smaller.
+fn converge_by_stepping(points: &[Point], start: i64) -> i64 {
+    let mut t = start;
+    let mut area = area_at(points, t);
+    let direction = if area_at(points, t + 1) < area { 1 } else { -1 };
+    loop {
+        let next = area_at(points, t + direction);
+        if next >= area {
+            return t;
+        }
+        t += direction;
+        area = next;
+    }
+}
+
+// The time at which the points are least spread out, in the sense of the
+// sum of the variances of their x and y coordinates. That is a quadratic in
+// t, so it's smallest at t = -cov(p, v) / var(v), summed over both axes.
+// Returns None if all points move at the same velocity and never get closer.
+fn least_spread_time(points: &[Point]) -> Option<i64> {
+    let n = points.len() as i128;
+    let mut numerator = 0i128;
+    let mut denominator = 0i128;
+    for axis in 0..2 {
+        let (mut sum_p, mut sum_v, mut sum_pv, mut sum_vv) = (0i128, 0i128, 0i128, 0i128);
+        for point in points.iter() {
+            let (p, v) = if axis == 0 { (point.x, point.vx) } else { (point.y, point.vy) };
+            let (p, v) = (p as i128, v as i128);
+            sum_p += p;
+            sum_v += v;
+            sum_pv += p * v;
+            sum_vv += v * v;
+        }
+        // Both scaled by n squared, which cancels out.
+        numerator += n * sum_pv - sum_p * sum_v;
+        denominator += n * sum_vv - sum_v * sum_v;
+    }
+    if denominator == 0 {
+        return None;
+    }
+
+    // Rounded to the nearest second.
+    Some((-2 * numerator + denominator).div_euclid(2 * denominator) as i64)
+}
+
+// Starts from the time the points are least spread out, and steps from
+// there to where the bounding box is smallest.
+fn find_message_time(points: &[Point]) -> i64 {
+    converge_by_stepping(points, least_spread_time(points).unwrap_or(0))
+}
+
+// The part of the sky that is `width` by `height` cells from (x, y), drawn
+// as `columns` by `rows` pixels. A pixel is lit if any point falls in the
+// cells it covers, so a large sky can be shrunk and a small one blown up.
+#[derive(Clone, Copy, Debug)]
+struct Viewport {
+    x: i64,
+    y: i64,
+    width: i64,
+    height: i64,
+    columns: usize,
+    rows: usize,
 }
 
-fn print_points(points: &Vec<Point>) -> () {
-    let mut min_x = 100000i64;
-    let mut max_x = -100000i64;
-    let mut min_y = 100000i64;
-    let mut max_y = -100000i64;
+impl Viewport {
+    // The bounding box (min_x, min_y, max_x, max_y), shrunk to fit in at
+    // most `max_columns` columns and `max_rows` rows, keeping its aspect
+    // ratio. Whichever of the two limits is tighter decides the scale.
+    fn around(bounding_box: (i64, i64, i64, i64), max_columns: usize, max_rows: usize) -> Viewport {
+        let (min_x, min_y, max_x, max_y) = bounding_box;
+        let width = max_x - min_x + 1;
+        let height = max_y - min_y + 1;
+        let (w, h) = (width as i128, height as i128);
+        let (columns, rows) = if w * max_rows as i128 >= h * max_columns as i128 {
+            let columns = min(w, max_columns as i128);
+            (columns, max(1, h * columns / w))
+        } else {
+            let rows = min(h, max_rows as i128);
+            (max(1, w * rows / h), rows)
+        };
+        Viewport { x: min_x, y: min_y, width, height, columns: columns as usize, rows: rows as usize }
+    }
 
-    for p in points.iter() {
-        min_x = min(min_x, p.x);
-        min_y = min(min_y, p.y);
-        max_x = max(max_x, p.x);
-        max_y = max(max_y, p.y);
+    // The pixels that cell `offset` of `cells` covers out of `pixels`: one
+    // when shrinking, and several when blowing up.
+    fn pixel_range(offset: i64, cells: i64, pixels: usize) -> std::ops::Range<usize> {
+        let start = (offset as i128 * pixels as i128 / cells as i128) as usize;
+        let end = ((offset + 1) as i128 * pixels as i128 / cells as i128) as usize;
+        start..max(end, start + 1)
     }
 
-    let mut matrix = Vec::new();
-    for _y in min_y..=max_y {
-        let mut row = Vec::new();
-        for _x in min_x..=max_x {
-            row.push(' ');
+    // The number of points on each pixel, by row.
+    fn raster(&self, positions: &[(i64, i64)]) -> Vec<Vec<u32>> {
+        let mut raster = vec![vec![0u32; self.columns]; self.rows];
+        for &(x, y) in positions.iter() {
+            if x < self.x || y < self.y || x - self.x >= self.width || y - self.y >= self.height {
+                continue;
+            }
+            for row in Viewport::pixel_range(y - self.y, self.height, self.rows) {
+                for column in Viewport::pixel_range(x - self.x, self.width, self.columns) {
+                    raster[row][column] += 1;
+                }
+            }
         }
-        matrix.push(row);
+        raster
     }
+}
 
-    for p in points.iter() {
-        matrix[(p.y - min_y) as usize][(p.x - min_x) as usize] = '#';
+fn print_points(positions: &[(i64, i64)], viewport: &Viewport) {
+    for row in viewport.raster(positions) {
+        let line: String = row.iter().map(|&n| if n > 0 { '#' } else { ' ' }).collect();
+        println!("{}", line);
     }
+}
+
+// A black and white binary PBM, with points in black.
+fn write_pbm(raster: &[Vec<u32>], w: &mut impl Write) -> io::Result<()> {
+    let columns = raster.first().map_or(0, |row| row.len());
+    netpbm::write_pbm(w, columns, raster.len(), |x, y| raster[y][x] > 0)
+}
+
+// A binary PPM of a night sky, with pixels that more points fall on
+// shining brighter.
+fn write_ppm(raster: &[Vec<u32>], w: &mut impl Write) -> io::Result<()> {
+    let columns = raster.first().map_or(0, |row| row.len());
+    netpbm::write_ppm(w, columns, raster.len(), |x, y| match raster[y][x] {
+        0 => [8, 8, 32],
+        n => {
+            let v = min(255, 159 + 32 * n) as u8;
+            [v, v, v / 2]
+        }
+    })
+}
 
-    for line in matrix.iter() {
-        for c in line.iter() {
-            print!("{}", c);
+// Writes one image for every second from `from` up to and including `to`,
+// all through the same viewport, which by default takes in the whole sky
+// during that time.
+fn export_frames(points: &[Point], from: i64, to: i64, options: &FrameOptions) -> io::Result<()> {
+    let viewport = options.viewport.unwrap_or_else(|| {
+        // The edges of the sky move in straight lines, so the sky is at its
+        // widest at one end or the other of the time range.
+        let at = |t| bounding_box(points.iter().map(|p| p.position_at(t)));
+        let (a, b) = (at(from), at(to));
+        let sky = (min(a.0, b.0), min(a.1, b.1), max(a.2, b.2), max(a.3, b.3));
+        Viewport::around(sky, options.max_columns, options.max_rows)
+    });
+
+    std::fs::create_dir_all(&options.directory)?;
+    for (frame, t) in (from..=to).enumerate() {
+        let raster = viewport.raster(&positions_at(points, t));
+        let path = format!("{}/frame_{:05}.{}", options.directory, frame, if options.ppm { "ppm" } else { "pbm" });
+        let mut file = BufWriter::new(File::create(&path)?);
+        if options.ppm {
+            write_ppm(&raster, &mut file)?;
+        } else {
+            write_pbm(&raster, &mut file)?;
         }
-        println!("");
+        file.flush()?;
+    }
+    println!("Wrote {} frames of {}x{} pixels to {}", to - from + 1, viewport.columns, viewport.rows, options.directory);
+    Ok(())
+}
+
+struct FrameOptions {
+    viewport: Option<Viewport>,
+    max_columns: usize,
+    max_rows: usize,
+    directory: String,
+    ppm: bool,
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: day10 [--verify] [--view <X>,<Y>,<WIDTH>,<HEIGHT>] [--size <COLUMNS>[,<ROWS>]] [--frames <SECONDS>] [--format pbm|ppm] [--out <DIR>]");
+    std::process::exit(1);
+}
+
+// Comma separated numbers, at least `count` of them.
+fn parse_numbers<T: std::str::FromStr>(arg: Option<String>, count: usize) -> Vec<T> {
+    let numbers: Option<Vec<T>> = arg.as_deref().and_then(|a| a.split(',').map(|n| n.trim().parse().ok()).collect());
+    match numbers {
+        Some(numbers) if numbers.len() >= count => numbers,
+        _ => usage(),
     }
 }
 
 fn main() -> io::Result<()> {
+    let mut verify = false;
+    let mut view = None;
+    let mut size = None;
+    let mut frames = None;
+    let mut options = FrameOptions {
+        viewport: None,
+        max_columns: 320,
+        max_rows: 240,
+        directory: String::from("frames"),
+        ppm: false,
+    };
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--verify" => verify = true,
+            "--view" => view = Some(parse_numbers::<i64>(args.next(), 4)),
+            "--size" => size = Some(parse_numbers::<usize>(args.next(), 1)),
+            "--frames" => frames = Some(parse_numbers::<i64>(args.next(), 1)[0]),
+            "--out" => options.directory = args.next().unwrap_or_else(|| usage()),
+            "--format" => options.ppm = match args.next().as_deref() {
+                Some("pbm") => false,
+                Some("ppm") => true,
+                _ => usage(),
+            },
+            _ => usage(),
+        }
+    }
+
+    if let Some(view) = &view {
+        if view[2] <= 0 || view[3] <= 0 {
+            usage();
+        }
+        let (columns, rows) = match &size {
+            Some(size) if size.len() > 1 => (size[0], size[1]),
+            // Keeping the aspect ratio, and no taller than a default sized
+            // picture would be.
+            Some(size) => {
+                let rows = view[3] as i128 * size[0] as i128 / view[2] as i128;
+                if rows > options.max_rows as i128 {
+                    let columns = view[2] as i128 * options.max_rows as i128 / view[3] as i128;
+                    (max(1, columns) as usize, options.max_rows)
+                } else {
+                    (size[0], max(1, rows) as usize)
+                }
+            }
+            None => (view[2] as usize, view[3] as usize),
+        };
+        if columns == 0 || rows == 0 {
+            usage();
+        }
+        options.viewport = Some(Viewport { x: view[0], y: view[1], width: view[2], height: view[3], columns, rows });
+    } else if let Some(size) = &size {
+        options.max_columns = max(1, size[0]);
+        options.max_rows = max(1, size.get(1).copied().unwrap_or(options.max_rows));
+    }
+
     let mut input = Vec::new();
 
     let re = Regex::new(
@@ -70,29 +313,97 @@ fn main() -> io::Result<()> {
         }
     }
 
-    let mut time_elapsed = 0u32;
+    let time_elapsed = find_message_time(&input);
+    if verify {
+        let stepped = converge_by_stepping(&input, 0);
+        if stepped != time_elapsed {
+            println!("Stepping from 0 finds the message after {} seconds instead", stepped);
+            std::process::exit(1);
+        }
+    }
+    let positions = positions_at(&input, time_elapsed);
+
+    let viewport = options.viewport.unwrap_or_else(|| {
+        Viewport::around(bounding_box(positions.iter().copied()), options.max_columns, options.max_rows)
+    });
+    print_points(&positions, &viewport);
+    match ocr::read_text(&positions) {
+        Some(text) => println!("The message reads: {}", text),
+        None => println!("The message isn't in a font that can be read"),
+    }
+    println!("Message appears after {} seconds.", time_elapsed);
 
-    loop {
-        time_elapsed += 1;
+    if let Some(seconds) = frames {
+        export_frames(&input, time_elapsed - seconds, time_elapsed + seconds, &options)?;
+    }
+
+    Ok(())
+}
 
-        let mut min_y = 100000i64;
-        let mut max_y = -100000i64;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use adventofcode_rust::rng::Rng;
 
-        for p in input.iter_mut() {
-            p.x += p.vx;
-            p.y += p.vy;
+    // Points that spell out `message` at time `t`, each moving at some
+    // non-zero speed.
+    fn synthetic_points(message: &str, t: i64, rng: &mut Rng) -> Vec<Point> {
+        let mut points = Vec::new();
+        for (x, y) in ocr::render_text(message).unwrap() {
+            let (mut vx, mut vy) = (0, 0);
+            while vx == 0 && vy == 0 {
+                vx = rng.range(-5, 5);
+                vy = rng.range(-5, 5);
+            }
+            points.push(Point { x: x - vx * t, y: y - vy * t, vx, vy });
+        }
+        points
+    }
 
-            min_y = min(min_y, p.y);
-            max_y = max(max_y, p.y);
+    // Messages in every letter of the font.
+    fn messages() -> Vec<String> {
+        let letters = ocr::known_letters();
+        vec![letters[..8].to_string(), letters[8..].to_string(), "EPZKLJZG".to_string(), "X".to_string()]
+    }
+
+    #[test]
+    fn synthetic_messages_are_found_and_read() {
+        let mut rng = Rng(0x2018_1210);
+        for message in messages() {
+            for &t in [0, 1, 3, 10_000, -250, 3_000_000_000, -7_654_321_987].iter() {
+                let points = synthetic_points(&message, t, &mut rng);
+                let found = find_message_time(&points);
+                assert_eq!(found, t, "{}", message);
+                assert_eq!(ocr::read_text(&positions_at(&points, found)), Some(message.clone()));
+            }
         }
+    }
 
-        if max_y - min_y < 10 {
-            break;
+    #[test]
+    fn stepping_from_zero_finds_the_same_time() {
+        let mut rng = Rng(0x2018_1210);
+        for message in messages() {
+            for &t in [0, 1, 3, 10_000, -250].iter() {
+                let points = synthetic_points(&message, t, &mut rng);
+                assert_eq!(converge_by_stepping(&points, 0), t, "{}", message);
+            }
         }
     }
 
-    print_points(&input);
-    println!("Message appears after {} seconds.", time_elapsed);
+    // A tall, narrow sky has to be shrunk along both axes.
+    #[test]
+    fn spread_out_sky_fits_the_viewport() {
+        let viewport = Viewport::around((0, 0, 1, 1_999_999_999), 320, 240);
+        assert_eq!((viewport.columns, viewport.rows), (1, 240));
+        let raster = viewport.raster(&[(0, 0), (1, 1_999_999_999)]);
+        assert_eq!(raster.len(), 240);
+        assert_eq!((raster[0][0], raster[239][0]), (1, 1));
 
-    Ok(())
+        let viewport = Viewport::around((-5_000_000_000, 0, 5_000_000_000, 2), 320, 240);
+        assert_eq!((viewport.columns, viewport.rows), (320, 1));
+
+        // A small sky isn't blown up.
+        let viewport = Viewport::around((0, 0, 61, 9), 320, 240);
+        assert_eq!((viewport.columns, viewport.rows), (62, 10));
+    }
 }
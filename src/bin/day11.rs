@@ -1,106 +1,182 @@
 use std::io;
 use std::io::prelude::*;
 
-struct PowerGrid {
-    grid: [i32; 90000],
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Instant;
+
+use adventofcode_rust::summed_area::{Rectangle, SummedAreaTable};
+
+// The puzzle's power level for the fuel cell at 0-based (x, y).
+fn fuel_cell_power(serial: i32) -> impl Fn(usize, usize) -> i32 {
+    move |x, y| {
+        let rack_id = (x + 1) as i32 + 10;
+        let power_level = rack_id * (rack_id * (y + 1) as i32 + serial);
+        power_level / 100 % 10 - 5
+    }
 }
 
-impl PowerGrid {
-    fn new(serial: i32) -> PowerGrid {
-        let mut powerlevels: [i32; 90000] = [0; 90000];
-        for y in 0..300 as usize {
-            let mut rack_id = 10;
-
-            for x in 0..300 as usize {
-                rack_id += 1;
-
-                let mut power_level = rack_id * (rack_id * (y + 1) as i32 + serial);
-                power_level = power_level / 100 % 10 - 5;
+// Is `square` better than `best`? Ties go to the smaller square, as they do
+// when trying the sizes in increasing order.
+fn beats(square: &Rectangle<i32>, best: &Option<Rectangle<i32>>) -> bool {
+    match best {
+        Some(best) => square.sum > best.sum || (square.sum == best.sum && square.width < best.width),
+        None => true,
+    }
+}
 
-                powerlevels[y * 300 + x] = power_level;
+// Tries every square size in turn.
+fn largest_square_serial(grid: &SummedAreaTable<i32>) -> Option<Rectangle<i32>> {
+    let mut best = None;
+    for s in 1..=grid.width().min(grid.height()) {
+        if let Some(square) = grid.max_square(s) {
+            if beats(&square, &best) {
+                best = Some(square);
             }
         }
+    }
+    best
+}
 
-        let mut summed_area: [i32; 90000] = [0; 90000];
-        for y in 0..300 as usize {
-            for x in 0..300 as usize {
-                let mut sum = powerlevels[y * 300 + x];
-                if x > 0 {
-                    sum += summed_area[y * 300 + x - 1]
-                }
-                if y > 0 {
-                    sum += summed_area[(y - 1) * 300 + x]
+// An s by s square is four (s / 2) by (s / 2) squares, plus a cross of
+// 2s - 1 cells through the middle when s is odd. So none can sum to more
+// than four times the best half size square plus the most the cross can hold.
+fn square_bound(half_bound: i32, side: usize, max_cell: i32) -> i32 {
+    let cross = if side % 2 == 1 { (2 * side - 1) as i32 } else { 0 };
+    4 * half_bound + cross * max_cell
+}
+
+// Hands the square sizes out to `threads` workers, smallest first. A size is
+// skipped when the bound from its half size shows it can't beat the best
+// square found so far; its bound is kept in place of its best sum, so larger
+// sizes can still be bounded from it.
+fn largest_square_parallel(grid: &SummedAreaTable<i32>, threads: usize) -> Option<Rectangle<i32>> {
+    let max_side = grid.width().min(grid.height());
+    let max_cell = grid.max_square(1)?.sum;
+
+    let next_side = AtomicUsize::new(1);
+    let bounds: Mutex<Vec<Option<i32>>> = Mutex::new(vec![None; max_side + 1]);
+    let best: Mutex<Option<Rectangle<i32>>> = Mutex::new(None);
+
+    thread::scope(|scope| {
+        for _ in 0..threads.max(1) {
+            scope.spawn(|| loop {
+                let side = next_side.fetch_add(1, Ordering::Relaxed);
+                if side > max_side {
+                    break;
                 }
-                if x > 0 && y > 0 {
-                    sum -= summed_area[(y - 1) * 300 + x - 1]
+
+                let half_bound = bounds.lock().unwrap()[side / 2];
+                let best_sum = best.lock().unwrap().map(|b| b.sum);
+                if let (Some(half_bound), Some(best_sum)) = (half_bound, best_sum) {
+                    let bound = square_bound(half_bound, side, max_cell);
+                    if bound < best_sum {
+                        bounds.lock().unwrap()[side] = Some(bound);
+                        continue;
+                    }
                 }
 
-                summed_area[y * 300 + x] = sum;
-            }
+                let square = grid.max_square(side).unwrap();
+                bounds.lock().unwrap()[side] = Some(square.sum);
+                let mut best = best.lock().unwrap();
+                if beats(&square, &best) {
+                    *best = Some(square);
+                }
+            });
         }
+    });
 
-        PowerGrid { grid: summed_area }
-    }
+    best.into_inner().unwrap()
+}
 
-    fn get(&self, x: usize, y: usize) -> i32 {
-        self.grid[y * 300 + x]
-    }
+fn benchmark(threads: usize) {
+    for &size in [300, 600, 1000].iter() {
+        for &serial in [18, 42].iter() {
+            let grid = SummedAreaTable::new(size, size, fuel_cell_power(serial));
 
-    fn square(&self, x: usize, y: usize, side: usize) -> i32 {
-        let mut sum = self.get(x + side - 1, y + side - 1);
-        if x > 0 {
-            sum -= self.get(x - 1, y + side - 1)
-        }
-        if y > 0 {
-            sum -= self.get(x + side - 1, y - 1)
-        }
-        if x > 0 && y > 0 {
-            sum += self.get(x - 1, y - 1)
+            let start = Instant::now();
+            let serial_square = largest_square_serial(&grid);
+            let serial_time = start.elapsed();
+
+            let start = Instant::now();
+            let single_square = largest_square_parallel(&grid, 1);
+            let single_time = start.elapsed();
+
+            let start = Instant::now();
+            let parallel_square = largest_square_parallel(&grid, threads);
+            let parallel_time = start.elapsed();
+
+            assert_eq!(serial_square, single_square);
+            assert_eq!(serial_square, parallel_square);
+            println!("{}x{} grid, serial number {}: serial loop took {:?}, search took {:?} on 1 thread and {:?} on {} threads",
+                size, size, serial, serial_time, single_time, parallel_time, threads);
         }
-        sum
     }
 }
 
+fn usage() -> ! {
+    eprintln!("Usage: day11 [--width <CELLS>] [--height <CELLS>] [--threads <N>] [--bench]");
+    std::process::exit(1);
+}
+
 fn main() -> io::Result<()> {
+    let mut width = 300;
+    let mut height = 300;
+    let mut threads = None;
+    let mut bench = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--bench" {
+            bench = true;
+            continue;
+        }
+        let value = args.next().and_then(|v| v.parse().ok());
+        match (&arg[..], value) {
+            ("--width", Some(v)) => width = v,
+            ("--height", Some(v)) => height = v,
+            ("--threads", Some(v)) => threads = Some(v),
+            _ => usage(),
+        }
+    }
+
+    if bench {
+        let available = thread::available_parallelism().map(|n| n.get()).unwrap_or(1);
+        benchmark(threads.unwrap_or(available));
+        return Ok(());
+    }
+
     let mut input: i32 = 0;
     for line in io::stdin().lock().lines() {
         input = line.unwrap().parse().unwrap();
         break;
     }
 
-    let grid = PowerGrid::new(input);
-
-    let mut max_sum = -10000;
-    let mut max_3_square = (0, 0);
-    let mut max_square = (0, 0, 0);
+    let grid = SummedAreaTable::new(width, height, fuel_cell_power(input));
 
-    for s in 3..=300 as usize {
-        for y in 0..300 - s as usize {
-            for x in 0..300 - s as usize {
-                let square_sum = grid.square(x, y, s);
-
-                if square_sum > max_sum {
-                    max_sum = square_sum;
-                    max_square = (x, y, s);
-                }
-            }
-        }
-        if s == 3 {
-            max_3_square = (max_square.0, max_square.1);
-        }
+    match grid.max_square(3) {
+        Some(square) => println!(
+            "The X,Y coordinate of the most powerful 3x3 square: ({},{})",
+            square.x + 1,
+            square.y + 1
+        ),
+        None => println!("No 3x3 square fits in a {}x{} grid", width, height),
     }
 
-    println!(
-        "The X,Y coordinate of the most powerful 3x3 square: ({},{})",
-        max_3_square.0 + 1,
-        max_3_square.1 + 1
-    );
-    println!(
-        "The X,Y,size identifier of the square with the largest total power: ({},{},{})",
-        max_square.0 + 1,
-        max_square.1 + 1,
-        max_square.2
-    );
+    let largest = match threads {
+        Some(threads) => largest_square_parallel(&grid, threads),
+        None => largest_square_serial(&grid),
+    };
+    match largest {
+        Some(square) => println!(
+            "The X,Y,size identifier of the square with the largest total power: ({},{},{})",
+            square.x + 1,
+            square.y + 1,
+            square.width
+        ),
+        None => println!("There are no squares in a {}x{} grid", width, height),
+    }
 
     Ok(())
 }
@@ -2,29 +2,146 @@ use std::io;
 use std::io::prelude::*;
 
 use std::collections::VecDeque;
+use std::fs::File;
+use std::io::BufWriter;
+use std::time::Instant;
+
+use adventofcode_rust::netpbm;
+
+// The largest radius an automaton can have. Its rules take 2^(2r+1) entries,
+// and the packed row shifts words by up to r bits.
+const MAX_RADIUS: usize = 10;
+
+// A one-dimensional cellular automaton, in which the next state of a pot
+// depends on the pots up to `radius` away on either side of it.
+#[derive(Clone, Debug)]
+struct Automaton {
+    radius: usize,
+    // The next state for each neighbourhood, with the leftmost pot as the
+    // most significant bit of the index.
+    rules: Vec<bool>,
+}
+
+impl Automaton {
+    fn new(radius: usize, rules: Vec<bool>) -> Result<Automaton, String> {
+        if radius > MAX_RADIUS {
+            return Err(format!("radius {} is larger than {}", radius, MAX_RADIUS));
+        }
+        if rules.len() != 1 << (2 * radius + 1) {
+            return Err(format!("{} rules for radius {}, expected {}", rules.len(), radius, 1 << (2 * radius + 1)));
+        }
+        // With empty pots growing plants, the row would be infinite.
+        if rules[0] {
+            return Err(String::from("empty pots can't grow plants"));
+        }
+        Ok(Automaton { radius, rules })
+    }
+
+    // An elementary automaton by its Wolfram rule number: bit k of the
+    // number is the next state for the neighbourhood k.
+    fn wolfram(rule: u8) -> Result<Automaton, String> {
+        let rules = (0..8).map(|k| (rule >> k) & 1 == 1).collect();
+        Automaton::new(1, rules)
+    }
+
+    fn width(&self) -> usize {
+        2 * self.radius + 1
+    }
+}
+
+fn parse_pots(pots: &str) -> Option<Vec<bool>> {
+    pots.chars().map(|c| match c {
+        '#' => Some(true),
+        '.' => Some(false),
+        _ => None,
+    }).collect()
+}
+
+// Reads the initial state and the rules. Neighbourhoods without a rule leave
+// the pot empty, and all rules have to be for the same radius.
+fn parse_input(input: &str) -> Result<(Vec<bool>, Automaton), String> {
+    let mut lines = input.lines().enumerate();
+
+    let initial_state = match lines.next() {
+        Some((_, line)) => line.strip_prefix("initial state: ").and_then(parse_pots),
+        None => None,
+    };
+    let initial_state = match initial_state {
+        Some(pots) if !pots.is_empty() => pots,
+        _ => return Err(String::from("line 1: expected \"initial state: \" followed by pots")),
+    };
+
+    let mut radius = None;
+    let mut rules: Vec<Option<bool>> = Vec::new();
+    for (i, line) in lines {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let error = |what: &str| Err(format!("line {}: {}", i + 1, what));
+
+        let (pattern, result) = match line.find(" => ") {
+            Some(at) => (&line[..at], &line[at + 4..]),
+            None => return error("expected \"<pots> => <pot>\""),
+        };
+        let pattern = match parse_pots(pattern) {
+            Some(pattern) if pattern.len() % 2 == 1 => pattern,
+            _ => return error("expected an odd number of pots before \"=>\""),
+        };
+        let result = match parse_pots(result).as_deref() {
+            Some(&[pot]) => pot,
+            _ => return error("expected a single pot after \"=>\""),
+        };
+
+        let r = pattern.len() / 2;
+        if r > MAX_RADIUS {
+            return error(&format!("rule for radius {}, at most {} is supported", r, MAX_RADIUS));
+        }
+        match radius {
+            None => {
+                radius = Some(r);
+                rules = vec![None; 1 << pattern.len()];
+            }
+            Some(radius) if radius != r => return error(&format!("rule for radius {}, expected radius {}", r, radius)),
+            Some(_) => {}
+        }
+
+        let key = pattern.iter().fold(0, |key, &p| key * 2 + p as usize);
+        match rules[key] {
+            Some(other) if other != result => return error("contradicts an earlier rule"),
+            _ => rules[key] = Some(result),
+        }
+    }
+
+    let radius = match radius {
+        Some(radius) => radius,
+        None => return Err(String::from("no rules")),
+    };
+    let rules = rules.iter().map(|r| r.unwrap_or(false)).collect();
+    Ok((initial_state, Automaton::new(radius, rules)?))
+}
 
 struct PotRow {
     state: VecDeque<bool>,
     number_of_first_pot: i64,
-    rules: [bool; 32],
+    automaton: Automaton,
 }
 
 impl PotRow {
-    fn new(initial_state: &[u8], rules: [bool; 32]) -> PotRow {
-        let mut state: VecDeque<bool> = VecDeque::new();
-        for p in initial_state.iter() {
-            let p = if *p == b'#' { true } else { false };
-            state.push_back(p);
-        }
-        PotRow {
-            state,
+    fn new(initial_state: &[bool], automaton: Automaton) -> PotRow {
+        let mut row = PotRow {
+            state: initial_state.iter().copied().collect(),
             number_of_first_pot: 0,
-            rules,
-        }
+            automaton,
+        };
+        row.trim();
+        row
     }
 
+    // Adds enough empty pots on either side for every pot that could grow
+    // a plant to be in the row.
     fn expand(&mut self) {
-        for _i in 0..=3 {
+        for _i in 0..2 * self.automaton.radius {
             self.number_of_first_pot -= 1;
             self.state.push_front(false);
             self.state.push_back(false);
@@ -32,12 +149,12 @@ impl PotRow {
     }
 
     fn trim(&mut self) {
-        while *(self.state.front().unwrap()) == false {
+        while self.state.front() == Some(&false) {
             self.number_of_first_pot += 1;
             self.state.pop_front();
         }
 
-        while *(self.state.back().unwrap()) == false {
+        while self.state.back() == Some(&false) {
             self.state.pop_back();
         }
     }
@@ -57,59 +174,316 @@ impl PotRow {
     fn iterate(&mut self) {
         self.expand();
 
-        let mut new_state = VecDeque::with_capacity(self.state.capacity());
+        let width = self.automaton.width();
+        let mask = (1 << width) - 1;
         let l = self.state.len();
+        let mut new_state = VecDeque::with_capacity(l);
 
         let mut key: usize = 0;
-        for i in 0..5 {
-            key *= 2;
-            if self.state[i] {
-                key += 1;
+        for i in 0..l {
+            key = ((key << 1) | self.state[i] as usize) & mask;
+            if i + 1 >= width {
+                new_state.push_back(self.automaton.rules[key]);
             }
         }
+        self.state = new_state;
+        self.number_of_first_pot += self.automaton.radius as i64;
+        self.trim();
+    }
+}
 
-        for i in 0..l - 5 {
-            new_state.push_back(self.rules[key]);
-            key %= 16;
-            key *= 2;
-            if self.state[i + 5] {
-                key += 1;
+// The same row with the pots packed into the bits of u64 words, so that the
+// rules can be applied to 64 pots at a time. Bit b of word j is the pot
+// numbered `number_of_first_pot + 64 * j + b`.
+struct PackedRow {
+    words: Vec<u64>,
+    number_of_first_pot: i64,
+    automaton: Automaton,
+}
+
+impl PackedRow {
+    fn new(initial_state: &[bool], automaton: Automaton) -> PackedRow {
+        let mut words = vec![0u64; initial_state.len().div_ceil(64).max(1)];
+        for (i, &p) in initial_state.iter().enumerate() {
+            if p {
+                words[i / 64] |= 1 << (i % 64);
             }
         }
-        self.state = new_state;
-        self.number_of_first_pot += 2;
+        let mut row = PackedRow {
+            words,
+            number_of_first_pot: 0,
+            automaton,
+        };
+        row.trim();
+        row
+    }
+
+    // The pots `offset` places to the right of the pots in word j.
+    fn shifted(&self, j: usize, offset: i64) -> u64 {
+        let word = |j: usize| self.words.get(j).copied().unwrap_or(0);
+        let d = offset.unsigned_abs() as u32;
+        if offset == 0 {
+            word(j)
+        } else if offset > 0 {
+            (word(j) >> d) | (word(j + 1) << (64 - d))
+        } else {
+            let before = if j > 0 { word(j - 1) } else { 0 };
+            (word(j) << d) | (before >> (64 - d))
+        }
+    }
+
+    // Makes sure there are `radius` empty pots at either end, so that every
+    // pot that could grow a plant has a bit.
+    fn expand(&mut self) {
+        let radius = self.automaton.radius as u32;
+        if radius == 0 {
+            return;
+        }
+        if self.words[0] & ((1 << radius) - 1) != 0 {
+            self.words.insert(0, 0);
+            self.number_of_first_pot -= 64;
+        }
+        if self.words[self.words.len() - 1] >> (64 - radius) != 0 {
+            self.words.push(0);
+        }
+    }
+
+    fn trim(&mut self) {
+        let leading = self.words.iter().take_while(|&&w| w == 0).count().min(self.words.len() - 1);
+        self.words.drain(..leading);
+        self.number_of_first_pot += 64 * leading as i64;
+        while self.words.len() > 1 && self.words[self.words.len() - 1] == 0 {
+            self.words.pop();
+        }
+    }
+
+    fn iterate(&mut self) {
+        self.expand();
+
+        let radius = self.automaton.radius as i64;
+        let width = self.automaton.width();
+        let keys: Vec<usize> = (0..self.automaton.rules.len()).filter(|&k| self.automaton.rules[k]).collect();
+        let mut neighbours = vec![0u64; width];
+        let mut new_words = Vec::with_capacity(self.words.len());
+        for j in 0..self.words.len() {
+            for (n, d) in neighbours.iter_mut().zip(-radius..=radius) {
+                *n = self.shifted(j, d);
+            }
+            let mut word = 0;
+            for &key in keys.iter() {
+                // The leftmost pot is the most significant bit of the key.
+                let mut matches = !0u64;
+                for (i, n) in neighbours.iter().enumerate() {
+                    let bit = (key >> (width - 1 - i)) & 1;
+                    matches &= if bit == 1 { *n } else { !*n };
+                }
+                word |= matches;
+            }
+            new_words.push(word);
+        }
+        self.words = new_words;
         self.trim();
     }
+
+    fn sum_of_numbers(&self) -> i64 {
+        let mut sum = 0;
+        for (j, &word) in self.words.iter().enumerate() {
+            let first = self.number_of_first_pot + 64 * j as i64;
+            let mut word = word;
+            while word != 0 {
+                sum += first + word.trailing_zeros() as i64;
+                word &= word - 1;
+            }
+        }
+        sum
+    }
 }
 
-fn main() -> io::Result<()> {
-    let lines = io::stdin();
-    let mut lines = lines.lock().lines();
+// The row after each of a number of generations, for drawing them one
+// under the other: generations go down, pots go across.
+struct SpaceTime {
+    // The number of the first pot, and the pots from there on.
+    generations: Vec<(i64, Vec<bool>)>,
+    // The first generation that is the previous one moved along.
+    settled_at: Option<usize>,
+    first_pot: i64,
+    last_pot: i64,
+}
 
-    let initial_state = lines.next().unwrap().unwrap();
+impl SpaceTime {
+    // Records the row as it is, and after each of the next `generations`.
+    fn record(row: &mut PotRow, generations: usize) -> SpaceTime {
+        let mut recorded: Vec<(i64, Vec<bool>)> = Vec::with_capacity(generations + 1);
+        let mut settled_at = None;
+        for generation in 0..=generations {
+            if generation > 0 {
+                row.iterate();
+            }
+            let state: Vec<bool> = row.state.iter().copied().collect();
+            if settled_at.is_none() && recorded.last().map(|(_, s)| s) == Some(&state) {
+                settled_at = Some(generation);
+            }
+            recorded.push((row.number_of_first_pot, state));
+        }
 
-    lines.next();
-    let mut rules = [false; 32];
+        // Always show pot 0, so that the diagrams line up with the puzzle.
+        let first_pot = recorded.iter().map(|(first, _)| *first).min().unwrap_or(0).min(0);
+        let last_pot = recorded.iter().map(|(first, s)| first + s.len() as i64 - 1).max().unwrap_or(0).max(0);
+        SpaceTime {
+            generations: recorded,
+            settled_at,
+            first_pot,
+            last_pot,
+        }
+    }
 
-    for line in lines {
-        let line = line.unwrap();
-        let line = line.as_bytes();
+    fn pots(&self) -> impl Iterator<Item = i64> {
+        self.first_pot..=self.last_pot
+    }
 
-        let mut key: usize = 0;
-        if line[9] == b'#' {
-            for i in 0..5 {
-                key *= 2;
-                if line[i] == b'#' {
-                    key += 1;
-                }
+    fn has_plant(&self, generation: usize, pot: i64) -> bool {
+        let (first, state) = &self.generations[generation];
+        pot >= *first && state.get((pot - first) as usize) == Some(&true)
+    }
+
+    fn is_settled(&self, generation: usize) -> bool {
+        self.settled_at.is_some_and(|settled_at| generation >= settled_at)
+    }
+
+    // Like the puzzle's example, with every tenth pot numbered from top to
+    // bottom above its column.
+    fn to_text(&self) -> String {
+        let margin = format!("{}: ", self.generations.len() - 1).len();
+        let labels: Vec<String> = self.pots().map(|pot| {
+            if pot % 10 == 0 { pot.to_string() } else { String::new() }
+        }).collect();
+        let label_lines = labels.iter().map(|l| l.len()).max().unwrap_or(0);
+
+        let mut text = String::new();
+        for line in 0..label_lines {
+            text.push_str(&" ".repeat(margin));
+            for label in labels.iter() {
+                let skip = label_lines - label.len();
+                text.push(if line < skip { ' ' } else { label.as_bytes()[line - skip] as char });
+            }
+            text.push('\n');
+        }
+
+        for generation in 0..self.generations.len() {
+            text.push_str(&format!("{:>width$}: ", generation, width = margin - 2));
+            for pot in self.pots() {
+                text.push(if self.has_plant(generation, pot) { '#' } else { '.' });
+            }
+            if self.settled_at == Some(generation) {
+                text.push_str("  <- settled");
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    // A black and white binary PBM, with plants in black.
+    fn write_pbm(&self, w: &mut impl Write) -> io::Result<()> {
+        let width = self.pots().count();
+        netpbm::write_pbm(w, width, self.generations.len(), |x, generation| {
+            self.has_plant(generation, self.first_pot + x as i64)
+        })
+    }
+
+    // A binary PGM, with plants in black until the row settles and in dark
+    // grey after, and the column of pot 0 in light grey.
+    fn write_pgm(&self, w: &mut impl Write) -> io::Result<()> {
+        let width = self.pots().count();
+        netpbm::write_pgm(w, width, self.generations.len(), |x, generation| {
+            let pot = self.first_pot + x as i64;
+            if self.has_plant(generation, pot) {
+                if self.is_settled(generation) { 96 } else { 0 }
+            } else if pot == 0 {
+                208
+            } else {
+                255
+            }
+        })
+    }
+}
+
+fn write_image(path: &str, write: impl Fn(&mut BufWriter<File>) -> io::Result<()>) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write(&mut file)?;
+    file.flush()
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: day12 [--wolfram <RULE>] [--max-generations <N>] [--generations <N>] [--diagram] [--pbm <FILE>] [--pgm <FILE>] [--simulate <GENERATIONS>]...");
+    std::process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut wolfram = None;
+    let mut max_generations: i64 = 10_000;
+    let mut generations = 20;
+    let mut diagram = false;
+    let mut pbm = None;
+    let mut pgm = None;
+    let mut simulate = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "--diagram" {
+            diagram = true;
+            continue;
+        }
+        match (&arg[..], args.next()) {
+            ("--wolfram", Some(v)) => wolfram = Some(v.parse::<u8>().unwrap_or_else(|_| usage())),
+            ("--max-generations", Some(v)) => max_generations = v.parse().unwrap_or_else(|_| usage()),
+            ("--generations", Some(v)) => generations = v.parse().unwrap_or_else(|_| usage()),
+            ("--pbm", Some(v)) => pbm = Some(v),
+            ("--pgm", Some(v)) => pgm = Some(v),
+            ("--simulate", Some(v)) => simulate.push(v.parse::<i64>().unwrap_or_else(|_| usage())),
+            _ => usage(),
+        }
+    }
+
+    let mut input = String::new();
+    io::stdin().lock().read_to_string(&mut input)?;
+
+    let (initial_state, mut automaton) = match parse_input(&input) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("Invalid input: {}", e);
+            std::process::exit(1);
+        }
+    };
+    if let Some(rule) = wolfram {
+        automaton = match Automaton::wolfram(rule) {
+            Ok(automaton) => automaton,
+            Err(e) => {
+                eprintln!("Rule {} can't be used: {}", rule, e);
+                std::process::exit(1);
             }
-            rules[key] = true;
+        };
+    }
+
+    if diagram || pbm.is_some() || pgm.is_some() {
+        let space_time = SpaceTime::record(&mut PotRow::new(&initial_state, automaton), generations);
+        if diagram {
+            print!("{}", space_time.to_text());
+        }
+        if let Some(path) = pbm {
+            write_image(&path, |w| space_time.write_pbm(w))?;
         }
+        if let Some(path) = pgm {
+            write_image(&path, |w| space_time.write_pgm(w))?;
+        }
+        return Ok(());
     }
 
-    let mut row = PotRow::new(&initial_state.as_bytes()[15..], rules);
+    let mut row = PotRow::new(&initial_state, automaton.clone());
     let mut last_state = row.state.clone();
     let mut last_sum = row.sum_of_numbers();
+    // The generation the row settled in, its sum then, and how much the sum
+    // grows by every generation after.
+    let mut settled = None;
 
     let mut i = 1;
     loop {
@@ -126,6 +500,12 @@ fn main() -> io::Result<()> {
                 "The sum of all pot numbers after 50 billion iterations: {}",
                 sum + (50000000000 - i) * (sum - last_sum)
             );
+            settled = Some((i, sum, sum - last_sum));
+            break;
+        }
+
+        if i == max_generations {
+            println!("The row doesn't settle into a moving pattern within {} iterations", max_generations);
             break;
         }
 
@@ -134,5 +514,157 @@ fn main() -> io::Result<()> {
         i += 1;
     }
 
+    // Simulating the packed row that far has to agree with extrapolating
+    // from where the row settled.
+    simulate.sort_unstable();
+    let mut packed = PackedRow::new(&initial_state, automaton);
+    let mut generation = 0;
+    let mut agrees = true;
+    for &target in simulate.iter() {
+        let start = Instant::now();
+        while generation < target {
+            packed.iterate();
+            generation += 1;
+        }
+        let sum = packed.sum_of_numbers();
+        match settled {
+            Some((at, settled_sum, step)) if target >= at => {
+                let expected = settled_sum + (target - at) * step;
+                println!("Generation {}: simulated {}, extrapolated {} ({:?})", target, sum, expected, start.elapsed());
+                agrees &= sum == expected;
+            }
+            _ => println!("Generation {}: simulated {} ({:?})", target, sum, start.elapsed()),
+        }
+    }
+    if !agrees {
+        std::process::exit(1);
+    }
+
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn puzzle_input() -> (Vec<bool>, Automaton) {
+        parse_input(include_str!("../../data/day12.txt")).unwrap()
+    }
+
+    const RULES: &str = "...## => #\n..#.. => #\n.#... => #\n";
+
+    #[test]
+    fn rules_are_read_by_neighbourhood() {
+        let (initial_state, automaton) = parse_input(&format!("initial state: #..#\n\n{}", RULES)).unwrap();
+        assert_eq!(initial_state, vec![true, false, false, true]);
+        assert_eq!(automaton.radius, 2);
+        let set: Vec<usize> = (0..32).filter(|&k| automaton.rules[k]).collect();
+        assert_eq!(set, vec![0b00011, 0b00100, 0b01000]);
+    }
+
+    #[test]
+    fn bad_headers_are_rejected() {
+        for header in ["", "initial state:", "initial state: #.x#", "state: #..#"].iter() {
+            let error = parse_input(&format!("{}\n\n{}", header, RULES)).unwrap_err();
+            assert!(error.starts_with("line 1:"), "{:?}: {}", header, error);
+        }
+    }
+
+    fn parse_rules(rules: &str) -> Result<Automaton, String> {
+        parse_input(&format!("initial state: #\n\n{}", rules)).map(|(_, automaton)| automaton)
+    }
+
+    #[test]
+    fn malformed_rules_are_rejected() {
+        let cases = [
+            ("..#. => #\n", "line 3: expected an odd number of pots before \"=>\""),
+            ("..#.. => ##\n", "line 3: expected a single pot after \"=>\""),
+            ("..#.. #\n", "line 3: expected \"<pots> => <pot>\""),
+            ("", "no rules"),
+            ("..... => #\n", "empty pots can't grow plants"),
+        ];
+        for &(rules, expected) in cases.iter() {
+            assert_eq!(parse_rules(rules).unwrap_err(), expected, "{:?}", rules);
+        }
+    }
+
+    #[test]
+    fn rules_for_different_radii_are_rejected() {
+        let error = parse_rules("..#.. => #\n.#. => #\n").unwrap_err();
+        assert_eq!(error, "line 4: rule for radius 1, expected radius 2");
+    }
+
+    #[test]
+    fn contradicting_rules_are_rejected() {
+        assert!(parse_rules("..#.. => #\n..#.. => #\n").is_ok());
+        let error = parse_rules("..#.. => #\n\n..#.. => .\n").unwrap_err();
+        assert_eq!(error, "line 5: contradicts an earlier rule");
+    }
+
+    // Without a limit, these would take terabytes of rules or overflow the
+    // shift.
+    #[test]
+    fn oversized_rules_are_rejected() {
+        let error = parse_rules(&format!("{} => #\n", ".".repeat(41))).unwrap_err();
+        assert_eq!(error, "line 3: rule for radius 20, at most 10 is supported");
+        let error = parse_rules(&format!("{} => #\n", "#".repeat(129))).unwrap_err();
+        assert_eq!(error, "line 3: rule for radius 64, at most 10 is supported");
+        assert!(parse_rules(&format!("{}#{} => #\n", ".".repeat(10), ".".repeat(10))).is_ok());
+        assert!(Automaton::new(11, vec![false; 1 << 23]).is_err());
+    }
+
+    fn check_packed_row_matches(initial_state: &[bool], automaton: Automaton, generations: usize) {
+        let mut row = PotRow::new(initial_state, automaton.clone());
+        let mut packed = PackedRow::new(initial_state, automaton);
+        for generation in 1..=generations {
+            row.iterate();
+            packed.iterate();
+            assert_eq!(packed.sum_of_numbers(), row.sum_of_numbers(), "generation {}", generation);
+        }
+    }
+
+    #[test]
+    fn packed_row_matches_pot_row() {
+        let (initial_state, automaton) = puzzle_input();
+        check_packed_row_matches(&initial_state, automaton, 200);
+    }
+
+    #[test]
+    fn packed_row_matches_pot_row_for_wolfram_rules() {
+        for &rule in [30, 90, 110, 150].iter() {
+            check_packed_row_matches(&[true], Automaton::wolfram(rule).unwrap(), 200);
+        }
+    }
+
+    // Once the row settles, every later sum follows from the last two, and
+    // simulating that far has to give the same.
+    #[test]
+    fn extrapolated_sums_match_simulation() {
+        let (initial_state, automaton) = puzzle_input();
+        let mut row = PotRow::new(&initial_state, automaton.clone());
+        let mut last_state = row.state.clone();
+        let mut last_sum = row.sum_of_numbers();
+        let mut settled_at: i64 = 1;
+        loop {
+            row.iterate();
+            if last_state == row.state {
+                break;
+            }
+            assert!(settled_at < 10_000, "the row doesn't settle");
+            last_state = row.state.clone();
+            last_sum = row.sum_of_numbers();
+            settled_at += 1;
+        }
+        let sum = row.sum_of_numbers();
+
+        let mut packed = PackedRow::new(&initial_state, automaton);
+        let mut generation = 0;
+        for &later in [settled_at, settled_at + 1, settled_at + 1000, settled_at + 100_000].iter() {
+            while generation < later {
+                packed.iterate();
+                generation += 1;
+            }
+            assert_eq!(packed.sum_of_numbers(), sum + (later - settled_at) * (sum - last_sum), "generation {}", later);
+        }
+    }
+}
@@ -4,9 +4,9 @@ use std::io::prelude::*;
 use std::cmp::Ord;
 use std::cmp::Ordering;
 
-use std::collections::HashMap;
+use std::fmt;
 
-#[derive(Eq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum Direction {
     North,
     East,
@@ -14,42 +14,74 @@ enum Direction {
     West,
 }
 
-impl PartialEq for Direction {
-    fn eq(&self, other: &Direction) -> bool {
-        self == other
+const ALL_DIRECTIONS: [Direction; 4] = [Direction::North, Direction::East, Direction::South, Direction::West];
+
+impl Direction {
+    fn port(self) -> u8 {
+        match self {
+            Direction::North => 1,
+            Direction::East => 2,
+            Direction::South => 4,
+            Direction::West => 8,
+        }
+    }
+
+    fn opposite(self) -> Direction {
+        match self {
+            Direction::North => Direction::South,
+            Direction::East => Direction::West,
+            Direction::South => Direction::North,
+            Direction::West => Direction::East,
+        }
+    }
+
+    fn step(self, (x, y): (i64, i64)) -> (i64, i64) {
+        match self {
+            Direction::North => (x, y - 1),
+            Direction::East => (x + 1, y),
+            Direction::South => (x, y + 1),
+            Direction::West => (x - 1, y),
+        }
+    }
+
+    fn symbol(self) -> u8 {
+        match self {
+            Direction::North => b'^',
+            Direction::East => b'>',
+            Direction::South => b'v',
+            Direction::West => b'<',
+        }
     }
 }
 
-#[derive(Eq)]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 enum TurnDirection {
     Left,
     Straight,
     Right,
 }
 
-impl PartialEq for TurnDirection {
-    fn eq(&self, other: &TurnDirection) -> bool {
-        self == other
-    }
-}
-
-#[derive(Eq)]
+#[derive(Debug, Eq, PartialEq)]
 struct Cart {
+    id: usize,
     pos: (u32, u32),
     dir: Direction,
     next_turndir: TurnDirection,
+    crashed: bool,
 }
 
 impl Cart {
-    fn new(pos: (u32, u32), dir: Direction) -> Cart {
+    fn new(id: usize, pos: (u32, u32), dir: Direction) -> Cart {
         Cart {
+            id,
             pos,
             dir,
             next_turndir: TurnDirection::Left,
+            crashed: false,
         }
     }
 
-    fn turn_left(&mut self) -> () {
+    fn turn_left(&mut self) {
         self.dir = match self.dir {
             Direction::North => Direction::West,
             Direction::East => Direction::North,
@@ -58,7 +90,7 @@ impl Cart {
         };
     }
 
-    fn turn_right(&mut self) -> () {
+    fn turn_right(&mut self) {
         self.dir = match self.dir {
             Direction::North => Direction::East,
             Direction::East => Direction::South,
@@ -67,7 +99,7 @@ impl Cart {
         };
     }
 
-    fn move_forward(&mut self) -> () {
+    fn move_forward(&mut self) {
         match self.dir {
             Direction::North => {
                 self.pos.1 -= 1;
@@ -84,7 +116,7 @@ impl Cart {
         }
     }
 
-    fn process_crossing(&mut self) -> () {
+    fn process_crossing(&mut self) {
         match self.next_turndir {
             TurnDirection::Left => {
                 self.turn_left();
@@ -99,72 +131,86 @@ impl Cart {
             }
         }
     }
-}
 
-impl PartialOrd for Cart {
-    fn partial_cmp(&self, other: &Cart) -> Option<Ordering> {
-        Some(self.cmp(other))
+    // Turns the cart the way the track piece it just moved onto points it.
+    fn follow(&mut self, piece: u8) {
+        match piece {
+            b'+' => {
+                self.process_crossing();
+            }
+            b'/' => match self.dir {
+                Direction::West | Direction::East => {
+                    self.turn_left();
+                }
+                Direction::North | Direction::South => {
+                    self.turn_right();
+                }
+            },
+            b'\\' => match self.dir {
+                Direction::West | Direction::East => {
+                    self.turn_right();
+                }
+                Direction::North | Direction::South => {
+                    self.turn_left();
+                }
+            },
+            _ => {}
+        }
     }
 }
 
-impl PartialEq for Cart {
-    fn eq(&self, other: &Cart) -> bool {
-        self.pos.0 == other.pos.0 && self.pos.1 == other.pos.1
-    }
+// Carts move in reading order: top to bottom, then left to right.
+fn reading_order(a: &Cart, b: &Cart) -> Ordering {
+    a.pos.1.cmp(&b.pos.1).then(a.pos.0.cmp(&b.pos.0))
 }
 
-impl Ord for Cart {
-    fn cmp(&self, other: &Cart) -> Ordering {
-        match self.pos.1.cmp(&other.pos.1) {
-            Ordering::Equal => self.pos.0.cmp(&other.pos.0),
-            Ordering::Less => Ordering::Less,
-            Ordering::Greater => Ordering::Greater,
-        }
-    }
+#[derive(Debug, PartialEq)]
+struct Crash {
+    tick: u64,
+    pos: (u32, u32),
+    // The cart that moved, and the cart it ran into.
+    carts: (usize, usize),
 }
 
 struct Track {
     track: Vec<Vec<u8>>,
     carts: Vec<Cart>,
-    current_cart_index: usize,
+    tick: u64,
+    crash_sites: Vec<(u32, u32)>,
 }
 
 impl Track {
-    fn new(lines: std::io::Lines<std::io::StdinLock>) -> Track {
+    fn new(input: &str) -> Track {
         let mut carts = Vec::new();
         let mut track = Vec::new();
 
-        for (y, line) in lines.enumerate() {
+        for (y, line) in input.lines().enumerate() {
             let y = y as u32;
             let mut track_row: Vec<u8> = Vec::new();
-            let mut x = 0;
-            for b in line.unwrap().into_bytes() {
+            for (x, b) in line.bytes().enumerate() {
+                let pos = (x as u32, y);
+                let id = carts.len();
                 match b {
                     b'^' => {
-                        let cart = Cart::new((x, y), Direction::North);
-                        carts.push(cart);
+                        carts.push(Cart::new(id, pos, Direction::North));
                         track_row.push(b'|');
                     }
                     b'v' => {
-                        let cart = Cart::new((x, y), Direction::South);
-                        carts.push(cart);
+                        carts.push(Cart::new(id, pos, Direction::South));
                         track_row.push(b'|');
                     }
                     b'>' => {
-                        let cart = Cart::new((x, y), Direction::East);
-                        carts.push(cart);
+                        carts.push(Cart::new(id, pos, Direction::East));
                         track_row.push(b'-');
                     }
                     b'<' => {
-                        let cart = Cart::new((x, y), Direction::West);
-                        carts.push(cart);
+                        carts.push(Cart::new(id, pos, Direction::West));
                         track_row.push(b'-');
                     }
                     _ => {
                         track_row.push(b);
                     }
                 }
-                x += 1;
             }
             track.push(track_row);
         }
@@ -172,108 +218,398 @@ impl Track {
         Track {
             carts,
             track,
-            current_cart_index: 0,
+            tick: 0,
+            crash_sites: Vec::new(),
         }
     }
 
-    fn end_of_tick(&mut self) -> bool {
-        self.current_cart_index == self.carts.len()
+    // Moves every cart once. After each single move the cart is checked
+    // against all other carts still on the track, so any number of crashes
+    // can happen in one tick. Crashed carts stay in place until the end of
+    // the tick, but they no longer move or get hit.
+    fn tick(&mut self) -> Vec<Crash> {
+        self.tick += 1;
+        self.crash_sites.clear();
+        self.carts.sort_by(reading_order);
+
+        let mut crashes = Vec::new();
+        for i in 0..self.carts.len() {
+            if self.carts[i].crashed {
+                continue;
+            }
+
+            let cart = &mut self.carts[i];
+            cart.move_forward();
+            cart.follow(self.track[cart.pos.1 as usize][cart.pos.0 as usize]);
+            let pos = cart.pos;
+
+            let hit = (0..self.carts.len()).find(|&j| j != i && !self.carts[j].crashed && self.carts[j].pos == pos);
+            if let Some(j) = hit {
+                self.carts[i].crashed = true;
+                self.carts[j].crashed = true;
+                self.crash_sites.push(pos);
+                crashes.push(Crash { tick: self.tick, pos, carts: (self.carts[i].id, self.carts[j].id) });
+            }
+        }
+
+        self.carts.retain(|c| !c.crashed);
+        crashes
     }
+}
 
-    fn find_and_process_collision(&mut self) -> Option<(u32, u32)> {
-        let mut positions_found: HashMap<(u32, u32), usize> = HashMap::new();
-        let mut pos_found: Option<(u32, u32)> = None;
-
-        for (i, cart) in self.carts.iter().enumerate() {
-            if let Some(j) = positions_found.insert(cart.pos, i) {
-                let a;
-                let b;
-
-                if i > j {
-                    a = i;
-                    b = j;
-                } else {
-                    a = j;
-                    b = i;
-                }
+// The sides a track piece can connect to, as a bit mask of Direction::port
+// values. A curve can be either of two corners, depending on its neighbours.
+fn piece_options(piece: u8) -> Vec<u8> {
+    let (n, e, s, w) = (1, 2, 4, 8);
+    match piece {
+        b'-' => vec![e | w],
+        b'|' => vec![n | s],
+        b'+' => vec![n | e | s | w],
+        b'/' => vec![e | s, n | w],
+        b'\\' => vec![w | s, n | e],
+        _ => vec![],
+    }
+}
 
-                pos_found = Some(cart.pos);
-                if self.current_cart_index > a {
-                    self.current_cart_index -= 1;
-                }
-                if self.current_cart_index > b {
-                    self.current_cart_index -= 1;
+struct Loop {
+    length: usize,
+    intersections: Vec<(u32, u32)>,
+}
+
+// How the track pieces hang together. A loop is what a cart that always goes
+// straight across intersections drives around on, and a network is a set of
+// loops joined by intersections: carts can only ever meet within a network.
+struct Topology {
+    loops: Vec<Loop>,
+    networks: Vec<Vec<usize>>,
+    // For every cart id, the loop it starts on.
+    cart_loops: Vec<(usize, usize)>,
+}
+
+impl Track {
+    fn piece_at(&self, (x, y): (i64, i64)) -> u8 {
+        if x < 0 || y < 0 {
+            return b' ';
+        }
+        match self.track.get(y as usize).and_then(|row| row.get(x as usize)) {
+            Some(&piece) => piece,
+            None => b' ',
+        }
+    }
+
+    // Works out which sides every piece connects to, and checks that every
+    // connection is matched by the neighbouring piece and that every cart
+    // sits on track that continues both ways along its direction.
+    fn validate(&self) -> Result<Vec<Vec<u8>>, Vec<String>> {
+        let mut errors = Vec::new();
+        let mut ports: Vec<Vec<u8>> = self.track.iter().map(|row| vec![0; row.len()]).collect();
+
+        for (y, row) in self.track.iter().enumerate() {
+            for (x, &piece) in row.iter().enumerate() {
+                let pos = (x as i64, y as i64);
+                let options = piece_options(piece);
+                let fits = |option: &u8| ALL_DIRECTIONS.iter().all(|&d| {
+                    option & d.port() == 0 || piece_options(self.piece_at(d.step(pos))).iter().any(|o| o & d.opposite().port() != 0)
+                });
+                match options.iter().find(|o| fits(o)) {
+                    Some(&option) => ports[y][x] = option,
+                    None if options.len() > 1 => errors.push(format!("Curve at {},{} doesn't connect to its neighbours", x, y)),
+                    None => {}
                 }
+            }
+        }
 
-                self.carts.remove(a);
-                self.carts.remove(b);
+        let ports_at = |(x, y): (i64, i64)| -> u8 {
+            if x < 0 || y < 0 {
+                return 0;
+            }
+            *ports.get(y as usize).and_then(|row| row.get(x as usize)).unwrap_or(&0)
+        };
+        for (y, row) in ports.iter().enumerate() {
+            for (x, &p) in row.iter().enumerate() {
+                for &d in ALL_DIRECTIONS.iter() {
+                    if p & d.port() != 0 && ports_at(d.step((x as i64, y as i64))) & d.opposite().port() == 0 {
+                        errors.push(format!("Track at {},{} leads nowhere to the {:?}", x, y, d));
+                    }
+                }
+            }
+        }
 
-                break;
+        for cart in self.carts.iter() {
+            let p = ports_at((cart.pos.0 as i64, cart.pos.1 as i64));
+            if p & cart.dir.port() == 0 || p & cart.dir.opposite().port() == 0 {
+                errors.push(format!("Cart {} at {},{} is not on a piece of track", cart.id, cart.pos.0, cart.pos.1));
             }
         }
 
-        pos_found
+        if errors.is_empty() {
+            Ok(ports)
+        } else {
+            Err(errors)
+        }
     }
 
-    fn iterate(&mut self) -> () {
-        if self.current_cart_index == self.carts.len() {
-            self.carts.sort();
-            self.current_cart_index = 0;
+    // Needs the connections of a track that passed validation.
+    fn topology(&self, ports: &[Vec<u8>]) -> Topology {
+        // Every piece is on one loop, except intersections, which are on one
+        // loop going north-south and on one going east-west.
+        let axis = |d: Direction| match d {
+            Direction::North | Direction::South => 0,
+            Direction::East | Direction::West => 1,
+        };
+        let mut loop_of: Vec<Vec<[Option<usize>; 2]>> = ports.iter().map(|row| vec![[None, None]; row.len()]).collect();
+        let mut loops: Vec<Loop> = Vec::new();
+
+        for (y, row) in ports.iter().enumerate() {
+            for (x, &p) in row.iter().enumerate() {
+                for &start in [Direction::North, Direction::East].iter() {
+                    if p & start.port() == 0 || loop_of[y][x][axis(start)].is_some() {
+                        continue;
+                    }
+                    let id = loops.len();
+                    let mut current = Loop { length: 0, intersections: Vec::new() };
+                    let (mut pos, mut d) = ((x as i64, y as i64), start);
+                    loop {
+                        let (cx, cy) = (pos.0 as usize, pos.1 as usize);
+                        let p = ports[cy][cx];
+                        if p == 15 {
+                            if loop_of[cy][cx][axis(d)].is_some() {
+                                break;
+                            }
+                            loop_of[cy][cx][axis(d)] = Some(id);
+                            current.intersections.push((cx as u32, cy as u32));
+                        } else {
+                            if loop_of[cy][cx][0].is_some() {
+                                break;
+                            }
+                            loop_of[cy][cx] = [Some(id), Some(id)];
+                            // Leave through the side we didn't come in by.
+                            if pos != (x as i64, y as i64) || current.length > 0 {
+                                d = *ALL_DIRECTIONS.iter().find(|&&o| p & o.port() != 0 && o != d.opposite()).unwrap();
+                            }
+                        }
+                        current.length += 1;
+                        pos = d.step(pos);
+                    }
+                    loops.push(current);
+                }
+            }
         }
 
-        let cart = &mut self.carts[self.current_cart_index];
-
-        match self.track[cart.pos.1 as usize][cart.pos.0 as usize] as char {
-            '+' => {
-                cart.process_crossing();
+        // Join loops that share an intersection into networks.
+        let mut network: Vec<usize> = (0..loops.len()).collect();
+        fn root(network: &mut [usize], mut i: usize) -> usize {
+            while network[i] != i {
+                network[i] = network[network[i]];
+                i = network[i];
             }
-            '/' => match cart.dir {
-                Direction::West | Direction::East => {
-                    cart.turn_left();
-                }
-                Direction::North | Direction::South => {
-                    cart.turn_right();
-                }
-            },
-            '\\' => match cart.dir {
-                Direction::West | Direction::East => {
-                    cart.turn_right();
+            i
+        }
+        for row in loop_of.iter() {
+            for cell in row.iter() {
+                if let [Some(a), Some(b)] = *cell {
+                    let (a, b) = (root(&mut network, a), root(&mut network, b));
+                    network[a] = b;
                 }
-                Direction::North | Direction::South => {
-                    cart.turn_left();
+            }
+        }
+        let mut networks: Vec<Vec<usize>> = Vec::new();
+        let mut network_index: Vec<Option<usize>> = vec![None; loops.len()];
+        for i in 0..loops.len() {
+            let r = root(&mut network, i);
+            match network_index[r] {
+                Some(n) => networks[n].push(i),
+                None => {
+                    network_index[r] = Some(networks.len());
+                    networks.push(vec![i]);
                 }
-            },
-            _ => {}
+            }
         }
-        cart.move_forward();
 
-        self.current_cart_index += 1;
+        let mut cart_loops: Vec<(usize, usize)> = self.carts.iter()
+            .map(|c| (c.id, loop_of[c.pos.1 as usize][c.pos.0 as usize][axis(c.dir)].unwrap()))
+            .collect();
+        cart_loops.sort();
+
+        Topology { loops, networks, cart_loops }
+    }
+}
+
+impl Topology {
+    // Carts never leave their network, so once no network holds two carts
+    // there will be no more crashes.
+    fn carts_can_meet(&self, carts: &[Cart]) -> bool {
+        let mut counts = vec![0; self.networks.len()];
+        for cart in carts.iter() {
+            let l = self.cart_loops.iter().find(|c| c.0 == cart.id).unwrap().1;
+            let n = self.networks.iter().position(|network| network.contains(&l)).unwrap();
+            counts[n] += 1;
+        }
+        counts.iter().any(|&n| n > 1)
     }
 }
 
+impl fmt::Display for Topology {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let intersections: usize = self.loops.iter().map(|l| l.intersections.len()).sum::<usize>() / 2;
+        writeln!(f, "{} loops, {} intersections, {} separate networks", self.loops.len(), intersections, self.networks.len())?;
+        for (i, l) in self.loops.iter().enumerate() {
+            let carts: Vec<String> = self.cart_loops.iter().filter(|c| c.1 == i).map(|c| c.0.to_string()).collect();
+            writeln!(f, "Loop {}: {} pieces, {} intersections, carts: {}", i, l.length, l.intersections.len(),
+                if carts.is_empty() { "none".to_string() } else { carts.join(", ") })?;
+        }
+        for (i, network) in self.networks.iter().enumerate() {
+            let loops: Vec<String> = network.iter().map(|l| l.to_string()).collect();
+            writeln!(f, "Network {}: loops {}", i, loops.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// Draws the track with the carts on it, and an X wherever carts crashed
+// during the last tick.
+impl fmt::Display for Track {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut grid = self.track.clone();
+        for cart in self.carts.iter() {
+            grid[cart.pos.1 as usize][cart.pos.0 as usize] = cart.dir.symbol();
+        }
+        for &(x, y) in self.crash_sites.iter() {
+            grid[y as usize][x as usize] = b'X';
+        }
+        for row in grid.iter() {
+            writeln!(f, "{}", String::from_utf8_lossy(row))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(PartialEq)]
+enum RenderMode {
+    Never,
+    EveryTick,
+    CollisionTicks,
+}
+
 fn main() -> io::Result<()> {
-    let mut track = Track::new(io::stdin().lock().lines());
+    let mut render = RenderMode::Never;
+    let mut stop_at: Option<u64> = None;
+    let mut topology = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--render" => render = RenderMode::EveryTick,
+            "--render-collisions" => render = RenderMode::CollisionTicks,
+            "--stop-at" => stop_at = args.next().and_then(|n| n.parse().ok()),
+            "--topology" => topology = true,
+            _ => {
+                eprintln!("Usage: day13 [--render | --render-collisions] [--stop-at <TICK>] [--topology]");
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut input = String::new();
+    io::stdin().lock().read_to_string(&mut input).unwrap();
+    let mut track = Track::new(&input);
+    let ports = match track.validate() {
+        Ok(ports) => ports,
+        Err(errors) => {
+            for e in errors.iter() {
+                eprintln!("{}", e);
+            }
+            std::process::exit(1);
+        }
+    };
+    let layout = track.topology(&ports);
+    if topology {
+        print!("{}", layout);
+    }
+    if render != RenderMode::Never {
+        println!("Initial state:\n{}", track);
+    }
 
     let mut found = false;
-    loop {
-        track.iterate();
-        if let Some(pos) = track.find_and_process_collision() {
+    while track.carts.len() > 1 {
+        if !layout.carts_can_meet(&track.carts) {
+            println!("The {} carts left are on separate networks and will never crash", track.carts.len());
+            return Ok(());
+        }
+        let crashes = track.tick();
+        if let Some(crash) = crashes.first() {
             if !found {
-                println!("Position of the first collision: {},{}", pos.0, pos.1);
+                println!("Position of the first collision: {},{}", crash.pos.0, crash.pos.1);
                 found = true;
             }
         }
-        if track.end_of_tick() {
-            if track.carts.len() == 1 {
-                let cart = track.carts.pop().unwrap();
-                println!(
-                    "Position of the last cart at the end of the tick: {},{}",
-                    cart.pos.0, cart.pos.1
-                );
-                break;
-            }
+        if render == RenderMode::EveryTick || (render == RenderMode::CollisionTicks && !crashes.is_empty()) {
+            println!("After tick {}:\n{}", track.tick, track);
         }
+        if stop_at == Some(track.tick) {
+            println!("Stopped after tick {} with {} carts left", track.tick, track.carts.len());
+            return Ok(());
+        }
+    }
+
+    match track.carts.first() {
+        Some(cart) => println!(
+            "Position of the last cart at the end of the tick: {},{}",
+            cart.pos.0, cart.pos.1
+        ),
+        None => println!("No carts are left at the end of the tick"),
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs until at most one cart is left, returning all crashes and where
+    // the last cart is at the end of that tick, if there is one.
+    fn run_to_end(input: &str) -> (Vec<Crash>, Option<(u32, u32)>) {
+        let mut track = Track::new(input);
+        let mut crashes = Vec::new();
+        while track.carts.len() > 1 {
+            crashes.append(&mut track.tick());
+        }
+        (crashes, track.carts.first().map(|c| c.pos))
+    }
+
+    #[test]
+    fn first_example() {
+        let input = "/->-\\        \n|   |  /----\\\n| /-+--+-\\  |\n| | |  | v  |\n\\-+-/  \\-+--/\n  \\------/   \n";
+        let expected = vec![Crash { tick: 14, pos: (7, 3), carts: (0, 1) }];
+        assert_eq!(run_to_end(input), (expected, None));
+    }
+
+    #[test]
+    fn second_example() {
+        let input = "/>-<\\  \n|   |  \n| /<+-\\\n| | | v\n\\>+</ |\n  |   ^\n  \\<->/\n";
+        let expected = vec![
+            Crash { tick: 1, pos: (2, 0), carts: (1, 0) },
+            Crash { tick: 1, pos: (2, 4), carts: (5, 4) },
+            Crash { tick: 1, pos: (6, 4), carts: (6, 3) },
+            Crash { tick: 3, pos: (2, 4), carts: (7, 2) },
+        ];
+        assert_eq!(run_to_end(input), (expected, Some((6, 4))));
+    }
+
+    #[test]
+    fn three_carts_reaching_one_crossing() {
+        let input = "  |  \n  v  \n>-+-<\n  |  \n  |  \n";
+        let expected = vec![Crash { tick: 2, pos: (2, 2), carts: (1, 0) }];
+        assert_eq!(run_to_end(input), (expected, Some((2, 2))));
+    }
+
+    #[test]
+    fn back_to_back_crashes_into_carts_that_havent_moved() {
+        let expected = vec![
+            Crash { tick: 1, pos: (1, 0), carts: (0, 1) },
+            Crash { tick: 1, pos: (3, 0), carts: (2, 3) },
+        ];
+        assert_eq!(run_to_end("><><\n"), (expected, None));
+    }
+}
@@ -1,78 +1,284 @@
 use std::io;
 use std::io::prelude::*;
 
-fn num_to_vec_u8(num: usize) -> Vec<u8> {
-    let mut v = Vec::new();
-    let mut num = num;
-    while num > 0 {
-        v.push((num % 10) as u8);
-        num /= 10;
-    }
-    v.reverse();
-    v
+use std::collections::VecDeque;
+
+// Every recipe on the scoreboard in order, making new ones as they are needed.
+struct Scoreboard {
+    recipes: Vec<u8>,
+    elves: Vec<usize>,
+    next: usize,
 }
 
-fn check_for_match(recipes: &Vec<u8>, numv: &Vec<u8>) -> bool {
-    if recipes.len() < numv.len() {
-        false
-    } else {
-        &recipes[recipes.len() - numv.len()..] == &numv[..]
+impl Scoreboard {
+    // Elf i starts on recipe i, so there must be at least as many starting
+    // recipes as elves.
+    fn new(starting_recipes: &[u8], num_elves: usize) -> Scoreboard {
+        assert!(num_elves <= starting_recipes.len(), "every elf needs a starting recipe");
+        Scoreboard {
+            recipes: starting_recipes.to_vec(),
+            elves: (0..num_elves).collect(),
+            next: 0,
+        }
+    }
+
+    fn make_recipes(&mut self) {
+        let mut sum: usize = self.elves.iter().map(|&e| self.recipes[e] as usize).sum();
+        let start = self.recipes.len();
+        if sum == 0 {
+            self.recipes.push(0);
+        }
+        while sum > 0 {
+            self.recipes.push((sum % 10) as u8);
+            sum /= 10;
+        }
+        self.recipes[start..].reverse();
+
+        for elf in self.elves.iter_mut() {
+            *elf = (*elf + self.recipes[*elf] as usize + 1) % self.recipes.len();
+        }
     }
 }
 
-fn main() -> io::Result<()> {
-    let mut num = 0usize;
-    for line in io::stdin().lock().lines() {
-        num = line.unwrap().parse().unwrap();
+impl Default for Scoreboard {
+    fn default() -> Scoreboard {
+        Scoreboard::new(&[3, 7], 2)
     }
+}
 
-    let mut recipes: Vec<u8> = Vec::with_capacity(33554432);
-    recipes.push(3);
-    recipes.push(7);
+impl Iterator for Scoreboard {
+    type Item = u8;
 
-    let mut a: usize = 0;
-    let mut b: usize = 1;
+    fn next(&mut self) -> Option<u8> {
+        while self.next >= self.recipes.len() {
+            if self.elves.is_empty() {
+                return None;
+            }
+            self.make_recipes();
+        }
+        self.next += 1;
+        Some(self.recipes[self.next - 1])
+    }
+}
 
-    let mut i: usize = 0;
-    let mut found = 0usize;
+// An Aho-Corasick automaton over decimal digits, for finding many digit
+// sequences in a single pass.
+struct PatternMatcher {
+    // The state to go to from each state on each digit.
+    transitions: Vec<[usize; 10]>,
+    // The patterns that end in each state, longest first.
+    outputs: Vec<Vec<usize>>,
+    lengths: Vec<usize>,
+}
 
-    let numv = num_to_vec_u8(num);
-    let lastrecipe: u8 = *numv.last().unwrap();
+impl PatternMatcher {
+    fn new(patterns: &[Vec<u8>]) -> PatternMatcher {
+        let mut transitions = vec![[0usize; 10]];
+        let mut outputs = vec![Vec::new()];
+        let mut children: Vec<[Option<usize>; 10]> = vec![[None; 10]];
 
-    while i < num + 10 || found == 0 {
-        let newrecipe = recipes[a] + recipes[b];
+        for (i, pattern) in patterns.iter().enumerate() {
+            let mut state = 0;
+            for &digit in pattern.iter() {
+                let digit = digit as usize;
+                state = match children[state][digit] {
+                    Some(next) => next,
+                    None => {
+                        let next = transitions.len();
+                        transitions.push([0; 10]);
+                        outputs.push(Vec::new());
+                        children.push([None; 10]);
+                        children[state][digit] = Some(next);
+                        next
+                    }
+                };
+            }
+            outputs[state].push(i);
+        }
 
-        if newrecipe > 9 {
-            let next_recipe = newrecipe / 10;
-            recipes.push(next_recipe);
-            if found == 0 && next_recipe == lastrecipe {
-                if check_for_match(&recipes, &numv) {
-                    found = recipes.len() - numv.len();
+        // Fill in the transitions breadth first, so that the failure state
+        // of a state is done before the state itself.
+        let mut fail = vec![0usize; transitions.len()];
+        let mut queue = VecDeque::new();
+        for digit in 0..10 {
+            if let Some(next) = children[0][digit] {
+                transitions[0][digit] = next;
+                queue.push_back(next);
+            }
+        }
+        while let Some(state) = queue.pop_front() {
+            let inherited = outputs[fail[state]].clone();
+            outputs[state].extend(inherited);
+            for digit in 0..10 {
+                match children[state][digit] {
+                    Some(next) => {
+                        fail[next] = transitions[fail[state]][digit];
+                        transitions[state][digit] = next;
+                        queue.push_back(next);
+                    }
+                    None => transitions[state][digit] = transitions[fail[state]][digit],
                 }
             }
         }
 
-        let next_recipe = newrecipe % 10;
-        recipes.push(next_recipe);
-        if found == 0 && next_recipe == lastrecipe {
-            if check_for_match(&recipes, &numv) {
-                found = recipes.len() - numv.len();
+        PatternMatcher {
+            transitions,
+            outputs,
+            lengths: patterns.iter().map(|p| p.len()).collect(),
+        }
+    }
+
+    // The position at which each pattern first starts in `digits`, reading
+    // no further than needed to find them all.
+    fn first_occurrences(&self, digits: impl Iterator<Item = u8>) -> Vec<Option<usize>> {
+        let mut found = vec![None; self.lengths.len()];
+        let mut remaining = self.lengths.len();
+        for &i in self.outputs[0].iter() {
+            found[i] = Some(0);
+            remaining -= 1;
+        }
+
+        let mut state = 0;
+        for (position, digit) in digits.enumerate() {
+            if remaining == 0 {
+                break;
+            }
+            state = self.transitions[state][digit as usize];
+            for &i in self.outputs[state].iter() {
+                if found[i].is_none() {
+                    found[i] = Some(position + 1 - self.lengths[i]);
+                    remaining -= 1;
+                }
             }
         }
+        found
+    }
+}
+
+fn parse_digits(text: &str) -> Option<Vec<u8>> {
+    if text.is_empty() {
+        return None;
+    }
+    text.chars().map(|c| c.to_digit(10).map(|d| d as u8)).collect()
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: day14 [--start <DIGITS>] [--elves <N>] [--find <DIGITS>]... [--limit <RECIPES>]");
+    std::process::exit(1);
+}
+
+fn main() -> io::Result<()> {
+    let mut starting_recipes = vec![3, 7];
+    let mut num_elves = 2;
+    let mut extra_patterns = Vec::new();
+    let mut limit = usize::MAX;
 
-        a = (a + recipes[a] as usize + 1) % recipes.len();
-        b = (b + recipes[b] as usize + 1) % recipes.len();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (&arg[..], args.next()) {
+            ("--start", Some(v)) => starting_recipes = parse_digits(&v).unwrap_or_else(|| usage()),
+            ("--elves", Some(v)) => num_elves = v.parse().unwrap_or_else(|_| usage()),
+            ("--find", Some(v)) => extra_patterns.push(parse_digits(&v).unwrap_or_else(|| usage())),
+            ("--limit", Some(v)) => limit = v.parse().unwrap_or_else(|_| usage()),
+            _ => usage(),
+        }
+    }
+    if num_elves > starting_recipes.len() {
+        eprintln!("There are only {} starting recipes for {} elves", starting_recipes.len(), num_elves);
+        std::process::exit(1);
+    }
 
-        i += 1;
+    let mut input = String::new();
+    for line in io::stdin().lock().lines() {
+        input = line.unwrap().trim().to_string();
     }
 
-    print!("The next ten recipes: ");
-    for recipe in num..num + 10 {
-        print!("{}", recipes[recipe]);
+    // Part 1 counts recipes, so leading zeros don't matter there, but they
+    // are part of the sequence part 2 looks for.
+    let digits = match parse_digits(&input) {
+        Some(digits) => digits,
+        None => {
+            eprintln!("Expected a sequence of digits, found {:?}", input);
+            std::process::exit(1);
+        }
+    };
+
+    match input.parse::<usize>() {
+        Ok(count) => {
+            let scoreboard = Scoreboard::new(&starting_recipes, num_elves);
+            let next_ten: String = scoreboard.skip(count).take(10).map(|r| r.to_string()).collect();
+            println!("The next ten recipes: {}", next_ten);
+        }
+        Err(_) => println!("Too many recipes to make: {}", input),
     }
-    println!("");
 
-    println!("The sequence appears after: {}", found);
+    let mut patterns = vec![digits];
+    patterns.extend(extra_patterns);
+    let matcher = PatternMatcher::new(&patterns);
+    let found = matcher.first_occurrences(Scoreboard::new(&starting_recipes, num_elves).take(limit));
+
+    for (i, (pattern, position)) in patterns.iter().zip(found.iter()).enumerate() {
+        let digits: String = pattern.iter().map(|d| d.to_string()).collect();
+        let name = if i == 0 { String::from("The sequence") } else { digits };
+        match position {
+            Some(position) => println!("{} appears after: {}", name, position),
+            None => println!("{} doesn't appear in the first {} recipes", name, limit),
+        }
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The ten recipes after the first `count`.
+    fn next_ten(count: usize) -> String {
+        Scoreboard::default().skip(count).take(10).map(|r| r.to_string()).collect()
+    }
+
+    // The number of recipes before `digits` first appear.
+    fn recipes_before(digits: &str) -> Option<usize> {
+        let digits = parse_digits(digits).unwrap();
+        PatternMatcher::new(&[digits]).first_occurrences(Scoreboard::default())[0]
+    }
+
+    #[test]
+    fn part_1_examples() {
+        assert_eq!(next_ten(9), "5158916779");
+        assert_eq!(next_ten(5), "0124515891");
+        assert_eq!(next_ten(18), "9251071085");
+        assert_eq!(next_ten(2018), "5941429882");
+    }
+
+    #[test]
+    fn part_2_examples() {
+        assert_eq!(recipes_before("51589"), Some(9));
+        assert_eq!(recipes_before("01245"), Some(5));
+        assert_eq!(recipes_before("92510"), Some(18));
+        assert_eq!(recipes_before("59414"), Some(2018));
+    }
+
+    // Leading zeros don't change the number of recipes in part 1.
+    #[test]
+    fn leading_zeros_in_a_count() {
+        assert_eq!(next_ten("0".parse().unwrap()), "3710101245");
+        assert_eq!(next_ten("005".parse().unwrap()), "0124515891");
+    }
+
+    // But they are part of the sequence in part 2.
+    #[test]
+    fn leading_zeros_in_a_sequence() {
+        assert_eq!(recipes_before("00"), Some(6152));
+        assert_eq!(recipes_before("012"), Some(5));
+    }
+
+    // Single digits, including the two starting recipes.
+    #[test]
+    fn single_digits() {
+        assert_eq!(recipes_before("3"), Some(0));
+        assert_eq!(recipes_before("7"), Some(1));
+        assert_eq!(recipes_before("0"), Some(3));
+    }
+}
@@ -7,12 +7,13 @@ use nom::{
     bytes::complete::tag,
     character::complete::{char as parse_char, one_of},
     combinator::{map, peek, recognize},
-    multi::{many1, separated_list},
-    sequence::{delimited},
+    multi::{many0, many1},
+    sequence::{delimited, pair, preceded},
     IResult,
 };
 
 use std::collections::HashMap;
+use std::fmt;
 
 #[derive(Debug)]
 enum RouteSpec<'a> {
@@ -29,9 +30,19 @@ fn parse_route(input: &str) -> IResult<&str, RouteSpec> {
     map(recognize(many1(one_of("NESW"))), |s| RouteSpec::Route(s))(input)
 }
 
+fn parse_branch(input: &str) -> IResult<&str, RouteSpec<'_>> {
+    alt((parse_routespec, parse_empty_route))(input)
+}
+
 fn parse_branches(input: &str) -> IResult<&str, RouteSpec> {
-    let parser = delimited(parse_char('('), separated_list(parse_char('|'), alt((parse_routespec, parse_empty_route))), parse_char(')'));
-    map(parser, |v| RouteSpec::Branches(v))(input)
+    // separated_list refuses a first element that consumes nothing, so an
+    // empty first branch as in "(|N)" needs to be handled by hand.
+    let branches = pair(parse_branch, many0(preceded(parse_char('|'), parse_branch)));
+    let parser = delimited(parse_char('('), branches, parse_char(')'));
+    map(parser, |(first, mut rest)| {
+        rest.insert(0, first);
+        RouteSpec::Branches(rest)
+    })(input)
 }
 
 fn parse_routespec(input: &str) -> IResult<&str, RouteSpec> {
@@ -40,7 +51,113 @@ fn parse_routespec(input: &str) -> IResult<&str, RouteSpec> {
 }
 
 fn parse_regex(input: &str) -> IResult<&str, RouteSpec> {
-    delimited(parse_char('^'), parse_routespec, tag("$\n"))(input)
+    delimited(parse_char('^'), parse_routespec, tag("$"))(input)
+}
+
+#[derive(Debug, PartialEq)]
+enum ParseErrorKind {
+    UnexpectedChar(char),
+    UnbalancedOpen,
+    UnbalancedClose,
+    EmptyAlternation,
+    MissingTerminator,
+    TrailingGarbage,
+}
+
+#[derive(Debug, PartialEq)]
+struct ParseError {
+    offset: usize,
+    kind: ParseErrorKind,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ParseErrorKind::UnexpectedChar(c) => write!(f, "unexpected character {:?}", c)?,
+            ParseErrorKind::UnbalancedOpen => write!(f, "unbalanced '('")?,
+            ParseErrorKind::UnbalancedClose => write!(f, "unbalanced ')'")?,
+            ParseErrorKind::EmptyAlternation => write!(f, "empty alternation '()'")?,
+            ParseErrorKind::MissingTerminator => write!(f, "missing '$'")?,
+            ParseErrorKind::TrailingGarbage => write!(f, "trailing characters after '$'")?,
+        }
+        write!(f, " at byte offset {}", self.offset)
+    }
+}
+
+// The nom parser only tells us that something failed, so before handing the
+// input to it we walk the regex once to find out what went wrong and where.
+fn validate_regex(input: &str) -> Result<(), ParseError> {
+    let error = |offset, kind| Err(ParseError { offset, kind });
+    let mut chars = input.char_indices();
+
+    match chars.next() {
+        Some((_, '^')) => {}
+        Some((i, c)) => return error(i, ParseErrorKind::UnexpectedChar(c)),
+        None => return error(0, ParseErrorKind::MissingTerminator),
+    }
+
+    // Offsets of the currently open parentheses, and the offset of the last
+    // character that was read.
+    let mut open: Vec<usize> = Vec::new();
+    let mut previous = 0;
+    let mut end = input.len();
+
+    for (i, c) in chars.by_ref() {
+        match c {
+            'N' | 'E' | 'S' | 'W' => {}
+            '(' => open.push(i),
+            '|' => {
+                if open.is_empty() {
+                    return error(i, ParseErrorKind::UnexpectedChar(c));
+                }
+            }
+            ')' => match open.pop() {
+                Some(start) if start == previous => return error(start, ParseErrorKind::EmptyAlternation),
+                Some(_) => {}
+                None => return error(i, ParseErrorKind::UnbalancedClose),
+            },
+            '$' => {
+                if let Some(start) = open.pop() {
+                    return error(start, ParseErrorKind::UnbalancedOpen);
+                }
+                if previous == 0 {
+                    return error(i, ParseErrorKind::UnexpectedChar(c));
+                }
+                for (i, c) in chars {
+                    if !c.is_whitespace() {
+                        return error(i, ParseErrorKind::TrailingGarbage);
+                    }
+                }
+                return Ok(());
+            }
+            // Trailing whitespace ends the input, but whitespace inside the
+            // regex is just a character that doesn't belong there.
+            _ if c.is_whitespace() && input[i..].trim().is_empty() => {
+                end = i;
+                break;
+            }
+            _ => return error(i, ParseErrorKind::UnexpectedChar(c)),
+        }
+        previous = i;
+    }
+
+    match open.pop() {
+        Some(start) => error(start, ParseErrorKind::UnbalancedOpen),
+        None => error(end, ParseErrorKind::MissingTerminator),
+    }
+}
+
+fn parse_input(input: &str) -> Result<RouteSpec<'_>, ParseError> {
+    validate_regex(input)?;
+    match parse_regex(input) {
+        Ok((_rest, route)) => Ok(route),
+        Err(nom::Err::Error((rest, _))) | Err(nom::Err::Failure((rest, _))) => {
+            let offset = input.len() - rest.len();
+            let c = rest.chars().next().unwrap_or('$');
+            Err(ParseError { offset, kind: ParseErrorKind::UnexpectedChar(c) })
+        }
+        Err(nom::Err::Incomplete(_)) => Err(ParseError { offset: input.len(), kind: ParseErrorKind::MissingTerminator }),
+    }
 }
 
 type Distance = u32;
@@ -102,7 +219,13 @@ fn main() -> io::Result<()> {
     io::stdin().lock().read_to_string(&mut input).unwrap();
     let input = &input[..];
 
-    let (_rest, route) = parse_regex(input).unwrap();
+    let route = match parse_input(input) {
+        Ok(route) => route,
+        Err(e) => {
+            eprintln!("Invalid regex: {}", e);
+            std::process::exit(1);
+        }
+    };
 
     let mut offset = Coords(0, 0);
     let distances = compute_distances(&route, &mut offset, HashMap::new());
@@ -123,3 +246,63 @@ fn main() -> io::Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn error(input: &str) -> (usize, ParseErrorKind) {
+        let e = parse_input(input).unwrap_err();
+        (e.offset, e.kind)
+    }
+
+    #[test]
+    fn unexpected_characters() {
+        assert_eq!(error("NE$"), (0, ParseErrorKind::UnexpectedChar('N')));
+        assert_eq!(error("^NX$"), (2, ParseErrorKind::UnexpectedChar('X')));
+        assert_eq!(error("^N|E$"), (2, ParseErrorKind::UnexpectedChar('|')));
+        assert_eq!(error("^$"), (1, ParseErrorKind::UnexpectedChar('$')));
+        assert_eq!(error("^NE SW$"), (3, ParseErrorKind::UnexpectedChar(' ')));
+        assert_eq!(error("^NE\nSW$\n"), (3, ParseErrorKind::UnexpectedChar('\n')));
+    }
+
+    #[test]
+    fn unbalanced_parentheses() {
+        assert_eq!(error("^N(E$"), (2, ParseErrorKind::UnbalancedOpen));
+        assert_eq!(error("^N(E(W|S)"), (2, ParseErrorKind::UnbalancedOpen));
+        assert_eq!(error("^N)$"), (2, ParseErrorKind::UnbalancedClose));
+    }
+
+    #[test]
+    fn empty_alternation() {
+        assert_eq!(error("^N()$"), (2, ParseErrorKind::EmptyAlternation));
+    }
+
+    #[test]
+    fn missing_terminator() {
+        assert_eq!(error(""), (0, ParseErrorKind::MissingTerminator));
+        assert_eq!(error("^NE"), (3, ParseErrorKind::MissingTerminator));
+        assert_eq!(error("^NE \n"), (3, ParseErrorKind::MissingTerminator));
+    }
+
+    #[test]
+    fn trailing_garbage() {
+        assert_eq!(error("^NE$x"), (4, ParseErrorKind::TrailingGarbage));
+        assert_eq!(error("^NE$ \nx"), (6, ParseErrorKind::TrailingGarbage));
+    }
+
+    #[test]
+    fn trailing_whitespace_is_allowed() {
+        for input in ["^NE$", "^NE$\n", "^NE$ \r\n", "^N(E|)W$\n\n"].iter() {
+            assert!(parse_input(input).is_ok(), "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn error_messages_give_the_offset() {
+        let e = parse_input("^NE SW$").unwrap_err();
+        assert_eq!(e.to_string(), "unexpected character ' ' at byte offset 3");
+        let e = parse_input("^NE").unwrap_err();
+        assert_eq!(e.to_string(), "missing '$' at byte offset 3");
+    }
+}
@@ -1,7 +1,7 @@
 use std::io;
 use std::io::prelude::*;
 
-use std::cmp::Ordering;
+use std::cmp::{max, min, Ordering};
 use std::collections::BinaryHeap;
 
 extern crate nom;
@@ -22,10 +22,23 @@ struct Nanobot {
 }
 
 impl Nanobot {
-    fn get_max_coordinate(&self) -> i64 {
-        let max = |a, b| if a > b { a } else { b };
-        let coord = max(self.x.abs(), self.y.abs());
-        max(coord, self.z.abs())
+    fn distance_to(&self, x: i64, y: i64, z: i64) -> i64 {
+        (self.x - x).abs() + (self.y - y).abs() + (self.z - z).abs()
+    }
+
+    fn covers(&self, x: i64, y: i64, z: i64) -> bool {
+        self.distance_to(x, y, z) <= self.r
+    }
+}
+
+fn count_covering(bots: &[Nanobot], x: i64, y: i64, z: i64) -> usize {
+    bots.iter().filter(|b| b.covers(x, y, z)).count()
+}
+
+fn count_in_range_of_strongest(bots: &[Nanobot]) -> usize {
+    match bots.iter().max_by_key(|b| b.r) {
+        Some(strongest) => bots.iter().filter(|b| strongest.covers(b.x, b.y, b.z)).count(),
+        None => 0,
     }
 }
 
@@ -42,13 +55,18 @@ fn parse_nanobot(input: &str) -> IResult<&str, Nanobot> {
     Ok((rest, Nanobot {x, y, z, r}))
 }
 
+// A box of integer points: `size_x` points starting at `o_x` along the x axis,
+// and likewise for y and z. A box with all sizes equal to 1 is a single point.
 #[derive(Debug, PartialEq, Eq)]
 struct SearchSquare {
     o_x: i64,
     o_y: i64,
     o_z: i64,
-    size: u64,
-    num_in_range: u64
+    size_x: u64,
+    size_y: u64,
+    size_z: u64,
+    num_in_range: u64,
+    num_fully_in_range: u64,
 }
 
 fn range_dist(x: i64, lo: i64, hi: i64) -> i64 {
@@ -61,45 +79,83 @@ fn range_dist(x: i64, lo: i64, hi: i64) -> i64 {
     }
 }
 
+fn range_far_dist(x: i64, lo: i64, hi: i64) -> i64 {
+    let (a, b) = ((x - lo).abs(), (x - hi).abs());
+    if a > b { a } else { b }
+}
+
+// Splits `size` points starting at `o` into at most two non-empty halves.
+fn split_range(o: i64, size: u64) -> Vec<(i64, u64)> {
+    if size == 1 {
+        return vec![(o, 1)];
+    }
+    let half = size / 2;
+    vec![(o, half), (o + half as i64, size - half)]
+}
+
 impl SearchSquare {
+    // The inclusive bounds of the box along each axis.
+    fn bounds(&self) -> [(i64, i64); 3] {
+        [
+            (self.o_x, self.o_x + self.size_x as i64 - 1),
+            (self.o_y, self.o_y + self.size_y as i64 - 1),
+            (self.o_z, self.o_z + self.size_z as i64 - 1),
+        ]
+    }
+
+    fn distance_to(&self, x: i64, y: i64, z: i64, dist: fn(i64, i64, i64) -> i64) -> i64 {
+        let [(x_lo, x_hi), (y_lo, y_hi), (z_lo, z_hi)] = self.bounds();
+        dist(x, x_lo, x_hi) + dist(y, y_lo, y_hi) + dist(z, z_lo, z_hi)
+    }
+
     fn distance_from_origin(&self) -> i64 {
-        let mut d = 0;
-        d += range_dist(0, self.o_x, self.o_x + self.size as i64);
-        d += range_dist(0, self.o_y, self.o_y + self.size as i64);
-        d += range_dist(0, self.o_z, self.o_z + self.size as i64);
-        d
+        self.distance_to(0, 0, 0, range_dist)
     }
 
     fn in_range(&self, bot: &Nanobot) -> bool {
-        let mut d = 0;
-        d += range_dist(bot.x, self.o_x, self.o_x + self.size as i64);
-        d += range_dist(bot.y, self.o_y, self.o_y + self.size as i64);
-        d += range_dist(bot.z, self.o_z, self.o_z + self.size as i64);
-        d <= bot.r
+        self.distance_to(bot.x, bot.y, bot.z, range_dist) <= bot.r
+    }
+
+    fn fully_in_range(&self, bot: &Nanobot) -> bool {
+        self.distance_to(bot.x, bot.y, bot.z, range_far_dist) <= bot.r
     }
 
-    fn new(x: i64, y: i64, z: i64, size: u64, bots: &Vec<Nanobot>) -> SearchSquare {
+    // Every point in a uniform square is in range of exactly the same bots,
+    // so there is no need to split it any further.
+    fn is_uniform(&self) -> bool {
+        self.num_in_range == self.num_fully_in_range
+    }
+
+    // The root box of a real input has about 1e9 points along each axis, so
+    // its volume doesn't fit in a u64.
+    fn num_points(&self) -> u128 {
+        self.size_x as u128 * self.size_y as u128 * self.size_z as u128
+    }
+
+    fn new(origin: (i64, i64, i64), size: (u64, u64, u64), bots: &[Nanobot]) -> SearchSquare {
         let mut square = SearchSquare {
-            o_x: x,
-            o_y: y,
-            o_z: z,
-            size,
-            num_in_range: 0
+            o_x: origin.0,
+            o_y: origin.1,
+            o_z: origin.2,
+            size_x: size.0,
+            size_y: size.1,
+            size_z: size.2,
+            num_in_range: 0,
+            num_fully_in_range: 0,
         };
-        square.num_in_range = bots.iter().filter(|b| square.in_range(&b)).count() as u64;
+        square.num_in_range = bots.iter().filter(|b| square.in_range(b)).count() as u64;
+        square.num_fully_in_range = bots.iter().filter(|b| square.fully_in_range(b)).count() as u64;
         square
     }
 
-    fn split(self, bots: &Vec<Nanobot>) -> Vec<SearchSquare> {
+    // The children partition the parent exactly: every point of the parent
+    // lies in exactly one child, whatever the sizes are.
+    fn split(self, bots: &[Nanobot]) -> Vec<SearchSquare> {
         let mut v = Vec::new();
-        let size = self.size / 2;
-        for p in 0..=1 {
-            for q in 0..=1 {
-                for r in 0..=1 {
-                    let o_x = self.o_x + p * size as i64;
-                    let o_y = self.o_y + q * size as i64;
-                    let o_z = self.o_z + r * size as i64;
-                    v.push(SearchSquare::new(o_x, o_y, o_z, size, bots));
+        for &(o_x, size_x) in split_range(self.o_x, self.size_x).iter() {
+            for &(o_y, size_y) in split_range(self.o_y, self.size_y).iter() {
+                for &(o_z, size_z) in split_range(self.o_z, self.size_z).iter() {
+                    v.push(SearchSquare::new((o_x, o_y, o_z), (size_x, size_y, size_z), bots));
                 }
             }
         }
@@ -111,7 +167,7 @@ impl Ord for SearchSquare {
     fn cmp(&self, other: &Self) -> Ordering {
         self.num_in_range.cmp(&other.num_in_range)
             .then(self.distance_from_origin().cmp(&other.distance_from_origin()).reverse())
-            .then(self.size.cmp(&other.size).reverse())
+            .then(self.num_points().cmp(&other.num_points()).reverse())
     }
 }
 
@@ -121,40 +177,195 @@ impl PartialOrd for SearchSquare {
     }
 }
 
+// Yields uniform squares ordered by the number of bots in range (highest
+// first), then by distance from the origin (closest first).
+struct CoverageSearch<'a> {
+    bots: &'a [Nanobot],
+    heap: BinaryHeap<SearchSquare>,
+}
+
+impl<'a> CoverageSearch<'a> {
+    fn new(bots: &'a [Nanobot]) -> CoverageSearch<'a> {
+        // The box holds the origin and every point in range of any bot, with
+        // a layer of points in range of none around it. A point outside is
+        // in range of no bot, and clamping it to the box gives a point on
+        // that layer which is closer to the origin, so nothing is missed.
+        let mut lo = [0, 0, 0];
+        let mut hi = [0, 0, 0];
+        for bot in bots.iter() {
+            for (axis, &c) in [bot.x, bot.y, bot.z].iter().enumerate() {
+                lo[axis] = min(lo[axis], c - bot.r - 1);
+                hi[axis] = max(hi[axis], c + bot.r + 1);
+            }
+        }
+
+        let size = |axis: usize| (hi[axis] - lo[axis] + 1) as u64;
+        let mut heap = BinaryHeap::new();
+        heap.push(SearchSquare::new((lo[0], lo[1], lo[2]), (size(0), size(1), size(2)), bots));
+        CoverageSearch { bots, heap }
+    }
+}
+
+impl<'a> Iterator for CoverageSearch<'a> {
+    type Item = SearchSquare;
+
+    fn next(&mut self) -> Option<SearchSquare> {
+        while let Some(square) = self.heap.pop() {
+            if square.is_uniform() {
+                return Some(square);
+            }
+            for s in square.split(self.bots) {
+                self.heap.push(s);
+            }
+        }
+        None
+    }
+}
+
+fn maximal_coverage_regions(bots: &[Nanobot]) -> Vec<SearchSquare> {
+    let mut search = CoverageSearch::new(bots).peekable();
+    let best = match search.peek() {
+        Some(square) => square.num_in_range,
+        None => return Vec::new(),
+    };
+    search.take_while(|s| s.num_in_range == best).collect()
+}
+
+// Returns up to k distinct coverage levels, highest first, each with the
+// shortest distance from the origin at which that level is reached.
+fn top_coverage_levels(bots: &[Nanobot], k: usize) -> Vec<(u64, i64)> {
+    let mut levels: Vec<(u64, i64)> = Vec::new();
+    for square in CoverageSearch::new(bots) {
+        if levels.len() == k {
+            break;
+        }
+        match levels.last() {
+            Some(&(n, _)) if n == square.num_in_range => {}
+            _ => levels.push((square.num_in_range, square.distance_from_origin())),
+        }
+    }
+    levels
+}
+
+fn usage() -> ! {
+    eprintln!("Usage: day23 [--point <X>,<Y>,<Z>]...");
+    std::process::exit(1);
+}
+
+fn parse_point(arg: Option<String>) -> Option<(i64, i64, i64)> {
+    let coordinates: Vec<i64> = arg?.split(',').map(|c| c.trim().parse().ok()).collect::<Option<_>>()?;
+    match coordinates[..] {
+        [x, y, z] => Some((x, y, z)),
+        _ => None,
+    }
+}
+
 fn main() -> io::Result<()> {
+    let mut queries = Vec::new();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--point" => queries.push(parse_point(args.next()).unwrap_or_else(|| usage())),
+            _ => usage(),
+        }
+    }
+
     let mut input = Vec::new();
     for line in io::stdin().lock().lines() {
         let line = &line.unwrap();
         let tuple = parse_nanobot(line).unwrap();
         input.push(tuple.1);
     }
-    let mut max_coordinate = 0;
-    for bot in input.iter() {
-        let c = bot.get_max_coordinate();
-        if c > max_coordinate {
-            max_coordinate = c;
-        }
+
+    println!("Nanobots in range of the strongest nanobot: {}", count_in_range_of_strongest(&input));
+
+    let regions = maximal_coverage_regions(&input);
+    let closest = &regions[0];
+    println!("Shortest Manhattan distance among points in range of most nanobots: {}", closest.distance_from_origin());
+
+    let points: u128 = regions.iter().map(|r| r.num_points()).sum();
+    println!("Points in range of {} nanobots: {} in {} region(s)", closest.num_in_range, points, regions.len());
+
+    for (n, d) in top_coverage_levels(&input, 3) {
+        println!("Closest point in range of {} nanobots is {} away", n, d);
     }
-    let mut size = 1i64;
-    while size < max_coordinate {
-        size *= 2;
+
+    for &(x, y, z) in queries.iter() {
+        println!("Nanobots in range of {},{},{}: {}", x, y, z, count_covering(&input, x, y, z));
     }
 
-    let square = SearchSquare::new(-size, -size, -size, (size * 2) as u64, &input);
-    let mut heap = BinaryHeap::new();
-    heap.push(square);
+    Ok(())
+}
 
-    loop {
-        let square = heap.pop().unwrap();
-        if square.size == 0 {
-            println!("Shortest Manhattan distance among points in range of most nanobots: {}", square.distance_from_origin());
-            break;
-        }
-        let split_squares = square.split(&input);
-        for s in split_squares {
-            heap.push(s);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use adventofcode_rust::rng::Rng;
+
+    // Compares the search against trying every point on small generated
+    // inputs.
+    #[test]
+    fn search_agrees_with_brute_force() {
+        let mut rng = Rng(0x2018_1223);
+        for _run in 0..500 {
+            let bots: Vec<Nanobot> = (0..rng.range(1, 12)).map(|_| Nanobot {
+                x: rng.range(-6, 6),
+                y: rng.range(-6, 6),
+                z: rng.range(-6, 6),
+                r: rng.range(0, 6),
+            }).collect();
+
+            // Every point past the bots' ranges, and then some.
+            let m = bots.iter().map(|b| max(max(b.x.abs(), b.y.abs()), b.z.abs()) + b.r).max().unwrap_or(0) + 2;
+            let mut levels: Vec<(u64, i64, u128)> = Vec::new();
+            for x in -m..=m {
+                for y in -m..=m {
+                    for z in -m..=m {
+                        let n = count_covering(&bots, x, y, z) as u64;
+                        let d = x.abs() + y.abs() + z.abs();
+                        match levels.iter_mut().find(|l| l.0 == n) {
+                            Some(l) => {
+                                if d < l.1 {
+                                    l.1 = d;
+                                }
+                                l.2 += 1;
+                            }
+                            None => levels.push((n, d, 1)),
+                        }
+                    }
+                }
+            }
+            levels.sort_by(|a, b| b.cmp(a));
+
+            let regions = maximal_coverage_regions(&bots);
+            let found = (regions[0].num_in_range, regions[0].distance_from_origin(), regions.iter().map(|r| r.num_points()).sum());
+            assert_eq!(found, levels[0], "{:?}", bots);
+            let expected_levels: Vec<(u64, i64)> = levels.iter().take(3).map(|l| (l.0, l.1)).collect();
+            assert_eq!(top_coverage_levels(&bots, 3), expected_levels, "{:?}", bots);
+            for r in regions.iter() {
+                assert_eq!(count_covering(&bots, r.o_x, r.o_y, r.o_z) as u64, r.num_in_range, "{:?}", bots);
+            }
         }
     }
 
-    Ok(())
+    // The region of a lone bot reaches past its centre.
+    #[test]
+    fn regions_cover_every_point_in_range() {
+        let bots = vec![Nanobot { x: 0, y: 0, z: 0, r: 1 }];
+        let regions = maximal_coverage_regions(&bots);
+        assert_eq!(regions.iter().map(|r| r.num_points()).sum::<u128>(), 7);
+
+        let bots = vec![Nanobot { x: 5, y: -3, z: 2, r: 2 }];
+        let regions = maximal_coverage_regions(&bots);
+        assert_eq!(regions.iter().map(|r| r.num_points()).sum::<u128>(), 25);
+        assert_eq!(top_coverage_levels(&bots, 3), vec![(1, 8), (0, 0)]);
+    }
+
+    #[test]
+    fn points_are_parsed_from_arguments() {
+        assert_eq!(parse_point(Some(String::from("1,-2,3"))), Some((1, -2, 3)));
+        assert_eq!(parse_point(Some(String::from("1,2"))), None);
+        assert_eq!(parse_point(Some(String::from("1,2,x"))), None);
+        assert_eq!(parse_point(None), None);
+    }
 }
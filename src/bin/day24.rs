@@ -7,13 +7,16 @@ use nom::{
     branch::alt,
     bytes::complete::{is_not, tag},
     character::complete::{alpha1, char as parse_char, digit1},
-    combinator::{map, map_res, opt},
+    combinator::{all_consuming, map, map_res, opt, verify},
     IResult,
-    multi::separated_list,
-    sequence::{delimited, separated_pair, terminated},
+    multi::{many0, separated_list},
+    sequence::{delimited, preceded, separated_pair, terminated},
 };
 
 use std::cmp::Ordering;
+use std::fmt;
+
+use adventofcode_rust::rng::Rng;
 
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
 enum Faction {
@@ -21,17 +24,217 @@ enum Faction {
     Infection
 }
 
+impl fmt::Display for Faction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Faction::ImmuneSystem => write!(f, "Immune System"),
+            Faction::Infection => write!(f, "Infection"),
+        }
+    }
+}
+
+// How the puzzle refers to a group: its army, and its 1-based position
+// within that army.
+#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+struct GroupLabel {
+    faction: Faction,
+    number: usize,
+}
+
+impl GroupLabel {
+    fn to_json(&self) -> String {
+        format!("{{\"army\":\"{}\",\"group\":{}}}", self.faction, self.number)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum BattleEvent {
+    Armies { round: u64, groups: Vec<(GroupLabel, u64)> },
+    TargetConsidered { round: u64, attacker: GroupLabel, defender: GroupLabel, damage: u64 },
+    TargetSelected { round: u64, attacker: GroupLabel, defender: GroupLabel, damage: u64 },
+    Attack { round: u64, attacker: GroupLabel, defender: GroupLabel, units_killed: u64 },
+}
+
+impl BattleEvent {
+    fn to_json(&self) -> String {
+        match self {
+            BattleEvent::Armies { round, groups } => {
+                let groups: Vec<String> = groups.iter()
+                    .map(|(label, units)| format!("{{\"army\":\"{}\",\"group\":{},\"units\":{}}}", label.faction, label.number, units))
+                    .collect();
+                format!("{{\"event\":\"armies\",\"round\":{},\"groups\":[{}]}}", round, groups.join(","))
+            },
+            BattleEvent::TargetConsidered { round, attacker, defender, damage } => {
+                format!("{{\"event\":\"target_considered\",\"round\":{},\"attacker\":{},\"defender\":{},\"damage\":{}}}",
+                    round, attacker.to_json(), defender.to_json(), damage)
+            },
+            BattleEvent::TargetSelected { round, attacker, defender, damage } => {
+                format!("{{\"event\":\"target_selected\",\"round\":{},\"attacker\":{},\"defender\":{},\"damage\":{}}}",
+                    round, attacker.to_json(), defender.to_json(), damage)
+            },
+            BattleEvent::Attack { round, attacker, defender, units_killed } => {
+                format!("{{\"event\":\"attack\",\"round\":{},\"attacker\":{},\"defender\":{},\"units_killed\":{}}}",
+                    round, attacker.to_json(), defender.to_json(), units_killed)
+            },
+        }
+    }
+}
+
+// Renders the events the way the puzzle description shows a battle. Target
+// selections have no textual form there, so they only show up in JSON.
+impl fmt::Display for BattleEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BattleEvent::Armies { groups, .. } => {
+                for faction in [Faction::ImmuneSystem, Faction::Infection].iter() {
+                    writeln!(f, "{}:", faction)?;
+                    let mut any = false;
+                    for (label, units) in groups.iter().filter(|(l, _)| l.faction == *faction) {
+                        writeln!(f, "Group {} contains {} units", label.number, units)?;
+                        any = true;
+                    }
+                    if !any {
+                        writeln!(f, "No groups remain.")?;
+                    }
+                }
+                Ok(())
+            },
+            BattleEvent::TargetConsidered { attacker, defender, damage, .. } => {
+                writeln!(f, "{} group {} would deal defending group {} {} damage", attacker.faction, attacker.number, defender.number, damage)
+            },
+            BattleEvent::TargetSelected { .. } => Ok(()),
+            BattleEvent::Attack { attacker, defender, units_killed, .. } => {
+                writeln!(f, "{} group {} attacks defending group {}, killing {} units", attacker.faction, attacker.number, defender.number, units_killed)
+            },
+        }
+    }
+}
+
+// The whole battle as text, with a blank line wherever the kind of event
+// changes.
+fn events_text(events: &[BattleEvent]) -> String {
+    let mut text = String::new();
+    let mut previous: Option<std::mem::Discriminant<BattleEvent>> = None;
+    for event in events.iter() {
+        let kind = std::mem::discriminant(event);
+        if let BattleEvent::TargetSelected { .. } = event {
+            continue;
+        }
+        if previous.is_some() && previous != Some(kind) {
+            text.push('\n');
+        }
+        text.push_str(&event.to_string());
+        previous = Some(kind);
+    }
+    text
+}
+
+fn print_events(events: &[BattleEvent], json: bool) {
+    if json {
+        for event in events.iter() {
+            println!("{}", event.to_json());
+        }
+    } else {
+        print!("{}", events_text(events));
+    }
+}
+
 #[derive(Debug, Eq, PartialEq, Hash, Clone)]
 struct Group<'a> {
     id: usize,
+    number: usize,
     faction: Faction,
     num_units: u64,
     hit_points: u64,
     weaknesses: Vec<&'a str>,
     immunities: Vec<&'a str>,
+    resistances: Vec<&'a str>,
     attack_damage: u64,
     attack_damage_type: &'a str,
     initiative: u64,
+    // Only kept to write the group back out the way it was read.
+    modifier_order: Vec<Modifier>,
+}
+
+#[derive(Debug, Eq, PartialEq, Hash, Clone, Copy)]
+enum Modifier {
+    Weak,
+    Immune,
+    Resistant,
+}
+
+impl Modifier {
+    // The order modifiers are written in when the input didn't give one.
+    const ALL: [Modifier; 3] = [Modifier::Weak, Modifier::Immune, Modifier::Resistant];
+
+    fn keyword(self) -> &'static str {
+        match self {
+            Modifier::Weak => "weak",
+            Modifier::Immune => "immune",
+            Modifier::Resistant => "resistant",
+        }
+    }
+}
+
+// Damage is multiplied by `numerator / denominator`, rounding down.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+struct Multiplier {
+    numerator: u64,
+    denominator: u64,
+}
+
+impl Multiplier {
+    fn apply(self, power: u64) -> u64 {
+        power * self.numerator / self.denominator
+    }
+}
+
+// A property of a group to order groups by, largest first.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum TieBreak {
+    EffectivePower,
+    Initiative,
+    Units,
+    HitPoints,
+}
+
+// The rules of the battle, so that variants of it can be fought with the
+// same armies. The default is the puzzle's rules.
+#[derive(Debug, Clone)]
+struct Rules {
+    normal: Multiplier,
+    weak: Multiplier,
+    immune: Multiplier,
+    resistant: Multiplier,
+    // The order in which groups select their targets.
+    selection_order: Vec<TieBreak>,
+    // The order in which a group prefers targets it would deal the same
+    // damage to.
+    target_order: Vec<TieBreak>,
+}
+
+impl Default for Rules {
+    fn default() -> Rules {
+        let ratio = |numerator, denominator| Multiplier { numerator, denominator };
+        Rules {
+            normal: ratio(1, 1),
+            weak: ratio(2, 1),
+            immune: ratio(0, 1),
+            resistant: ratio(1, 2),
+            selection_order: vec![TieBreak::EffectivePower, TieBreak::Initiative],
+            target_order: vec![TieBreak::EffectivePower, TieBreak::Initiative],
+        }
+    }
+}
+
+impl Rules {
+    // The smallest multiplier that still lets an attack do damage.
+    fn smallest_damaging_multiplier(&self) -> Option<Multiplier> {
+        [self.normal, self.weak, self.immune, self.resistant].iter()
+            .filter(|m| m.numerator > 0)
+            .min_by(|a, b| (a.numerator * b.denominator).cmp(&(b.numerator * a.denominator)))
+            .copied()
+    }
 }
 
 impl<'a> Group<'a> {
@@ -39,44 +242,112 @@ impl<'a> Group<'a> {
         self.num_units * self.attack_damage
     }
 
-    fn incur_attack(&mut self, attacker: &Self) -> u64 {
-        let mut damage = self.damage_dealt_by(attacker) / self.hit_points;
+    fn incur_damage(&mut self, damage: u64) -> u64 {
+        let mut killed = damage / self.hit_points;
 
-        if damage > self.num_units {
-            damage = self.num_units;
+        if killed > self.num_units {
+            killed = self.num_units;
         }
 
-        self.num_units -= damage;
+        self.num_units -= killed;
 
-        return damage;
+        killed
     }
 
-    fn damage_dealt_by(&self, attacker: &Self) -> u64 {
-        attacker.damage_dealt_to(self)
+    fn label(&self) -> GroupLabel {
+        GroupLabel { faction: self.faction.clone(), number: self.number }
     }
 
-    fn damage_dealt_to(&self, defender: &Self) -> u64 {
-        if defender.immunities.contains(&self.attack_damage_type) {
-            0
-        } else if defender.weaknesses.contains(&self.attack_damage_type) {
-            2 * self.effective_power()
+    fn damage_multiplier(&self, attack_damage_type: &str, rules: &Rules) -> Multiplier {
+        if self.immunities.contains(&attack_damage_type) {
+            rules.immune
+        } else if self.weaknesses.contains(&attack_damage_type) {
+            rules.weak
+        } else if self.resistances.contains(&attack_damage_type) {
+            rules.resistant
         } else {
-            self.effective_power()
+            rules.normal
+        }
+    }
+
+    fn modifier(&self, modifier: Modifier) -> &Vec<&'a str> {
+        match modifier {
+            Modifier::Weak => &self.weaknesses,
+            Modifier::Immune => &self.immunities,
+            Modifier::Resistant => &self.resistances,
+        }
+    }
+
+    fn tie_break_key(&self, tie_break: TieBreak) -> u64 {
+        match tie_break {
+            TieBreak::EffectivePower => self.effective_power(),
+            TieBreak::Initiative => self.initiative,
+            TieBreak::Units => self.num_units,
+            TieBreak::HitPoints => self.hit_points,
         }
     }
 }
 
-fn parse_weakness_or_immunity(input: &str) -> IResult<&str, (&str, Vec<&str>)> {
-    separated_pair(alt((tag("immune"), tag("weak"))), tag(" to "), separated_list(tag(", "), alpha1))(input)
+impl<'a> fmt::Display for Group<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} units each with {} hit points ", self.num_units, self.hit_points)?;
+
+        // Modifiers in the order they came in, followed by any that were
+        // added without one.
+        let unordered = Modifier::ALL.iter().filter(|m| !self.modifier_order.contains(m));
+        let parts: Vec<String> = self.modifier_order.iter().chain(unordered)
+            .filter(|&&m| !self.modifier(m).is_empty())
+            .map(|&m| format!("{} to {}", m.keyword(), self.modifier(m).join(", ")))
+            .collect();
+        if !parts.is_empty() {
+            write!(f, "({}) ", parts.join("; "))?;
+        }
+
+        write!(f, "with an attack that does {} {} damage at initiative {}", self.attack_damage, self.attack_damage_type, self.initiative)
+    }
 }
 
-fn parse_weaknesses_and_immunities(input: &str) -> IResult<&str, Vec<(&str, Vec<&str>)>> {
+// Both armies in the same format as the puzzle input. Groups without units
+// left are no longer part of their army, so they're left out, and an army
+// with none left is just its heading.
+struct Armies<'a, 'b>(&'b [Group<'a>]);
+
+impl<'a, 'b> fmt::Display for Armies<'a, 'b> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, faction) in [Faction::ImmuneSystem, Faction::Infection].iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{}:", faction)?;
+            for g in self.0.iter().filter(|g| g.faction == *faction && g.num_units > 0) {
+                writeln!(f, "{}", g)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_modifier(input: &str) -> IResult<&str, Modifier> {
+    alt((
+        map(tag("immune"), |_| Modifier::Immune),
+        map(tag("weak"), |_| Modifier::Weak),
+        map(tag("resistant"), |_| Modifier::Resistant),
+    ))(input)
+}
+
+fn parse_weakness_or_immunity(input: &str) -> IResult<&str, (Modifier, Vec<&str>)> {
+    separated_pair(parse_modifier, tag(" to "), separated_list(tag(", "), alpha1))(input)
+}
+
+fn parse_weaknesses_and_immunities(input: &str) -> IResult<&str, Vec<(Modifier, Vec<&str>)>> {
     separated_list(tag("; "), parse_weakness_or_immunity)(input)
 }
 
 fn parse_group(input: &str) -> IResult<&str, Group> {
     let mut weaknesses = Vec::new();
     let mut immunities = Vec::new();
+    let mut resistances = Vec::new();
+    let mut modifier_order = Vec::new();
 
     let (rest, num_units) = map_res(terminated(digit1, tag(" units each with ")), str::parse::<u64>)(input)?;
     let (rest, hit_points) = map_res(terminated(digit1, tag(" hit points ")), str::parse::<u64>)(rest)?;
@@ -84,11 +355,12 @@ fn parse_group(input: &str) -> IResult<&str, Group> {
 
     if let Some(weaknesses_immunities) = weaknesses_immunities {
         let (_rest, v) = parse_weaknesses_and_immunities(weaknesses_immunities)?;
-        for (value_type, damage_types) in v {
-            match value_type {
-                "immune" => { immunities = damage_types; }
-                "weak" => { weaknesses = damage_types; }
-                _ => { unreachable!(); }
+        for (modifier, damage_types) in v {
+            modifier_order.push(modifier);
+            match modifier {
+                Modifier::Immune => { immunities = damage_types; }
+                Modifier::Weak => { weaknesses = damage_types; }
+                Modifier::Resistant => { resistances = damage_types; }
             }
         }
     }
@@ -99,29 +371,90 @@ fn parse_group(input: &str) -> IResult<&str, Group> {
 
     Ok((rest, Group {
         id: 0,
+        number: 0,
         faction: Faction::Infection, // gets overwritten with correct value later
         num_units,
         hit_points,
         weaknesses,
         immunities,
+        resistances,
         attack_damage,
         attack_damage_type,
         initiative,
+        modifier_order,
     }))
 }
 
 fn parse_immune_system(input: &str) -> IResult<&str, Vec<Group>> {
     let parser = map(parse_group, |mut g| {g.faction = Faction::ImmuneSystem; g});
-    delimited(tag("Immune System:\n"), separated_list(parse_char('\n'), parser), tag("\n\n"))(input)
+    delimited(tag("Immune System:\n"), many0(terminated(parser, parse_char('\n'))), parse_char('\n'))(input)
 }
 
 fn parse_infection(input: &str) -> IResult<&str, Vec<Group>> {
     let parser = map(parse_group, |mut g| {g.faction = Faction::Infection; g});
-    delimited(tag("Infection:\n"), separated_list(parse_char('\n'), parser), tag("\n"))(input)
+    preceded(tag("Infection:\n"), many0(terminated(parser, parse_char('\n'))))(input)
 }
 
-fn compare_for_target_selection(a: &Group, b: &Group) -> Ordering {
-    a.effective_power().cmp(&b.effective_power()).then(a.initiative.cmp(&b.initiative)).reverse()
+fn compare_by(a: &Group, b: &Group, order: &[TieBreak]) -> Ordering {
+    order.iter()
+        .fold(Ordering::Equal, |o, &t| o.then(a.tie_break_key(t).cmp(&b.tie_break_key(t))))
+        .reverse()
+}
+
+fn compare_for_target_selection(a: &Group, b: &Group, rules: &Rules) -> Ordering {
+    compare_by(a, b, &rules.selection_order)
+}
+
+fn parse_multiplier(input: &str) -> IResult<&str, Multiplier> {
+    let number = |input| map_res(digit1, str::parse::<u64>)(input);
+    let (rest, numerator) = number(input)?;
+    let (rest, denominator) = opt(preceded(parse_char('/'), verify(number, |&d| d > 0)))(rest)?;
+    Ok((rest, Multiplier { numerator, denominator: denominator.unwrap_or(1) }))
+}
+
+fn parse_tie_break(input: &str) -> IResult<&str, TieBreak> {
+    alt((
+        map(tag("effective power"), |_| TieBreak::EffectivePower),
+        map(tag("initiative"), |_| TieBreak::Initiative),
+        map(tag("units"), |_| TieBreak::Units),
+        map(tag("hit points"), |_| TieBreak::HitPoints),
+    ))(input)
+}
+
+// Reads rules as "name = value" lines, for example "resistant = 1/2" or
+// "selection order = initiative, effective power". Anything not mentioned
+// keeps the puzzle's rule.
+fn parse_rules(input: &str) -> Result<Rules, String> {
+    let mut rules = Rules::default();
+    for (i, line) in input.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let error = |what: &str| format!("line {}: {}", i + 1, what);
+        let (name, value) = match line.find('=') {
+            Some(at) => (line[..at].trim(), line[at + 1..].trim()),
+            None => return Err(error("expected \"name = value\"")),
+        };
+
+        let multiplier = |value| all_consuming(parse_multiplier)(value)
+            .map(|(_, m)| m)
+            .map_err(|_| error("expected a multiplier such as 2 or 1/2"));
+        let tie_breaks = |value| all_consuming(separated_list(tag(", "), parse_tie_break))(value)
+            .map(|(_, v)| v)
+            .map_err(|_| error("expected a list of effective power, initiative, units or hit points"));
+
+        match name {
+            "normal" => rules.normal = multiplier(value)?,
+            "weak" => rules.weak = multiplier(value)?,
+            "immune" => rules.immune = multiplier(value)?,
+            "resistant" => rules.resistant = multiplier(value)?,
+            "selection order" => rules.selection_order = tie_breaks(value)?,
+            "target order" => rules.target_order = tie_breaks(value)?,
+            _ => return Err(error(&format!("unknown rule \"{}\"", name))),
+        }
+    }
+    Ok(rules)
 }
 
 fn get_groups_from_input(input: &str) -> Vec<Group> {
@@ -135,16 +468,13 @@ fn get_groups_from_input(input: &str) -> Vec<Group> {
         last_id += 1;
     }
 
-    groups
-}
-
-fn find_group_by_id(groups: &Vec<Group>, id: usize) -> usize {
-    for (i, g) in groups.iter().enumerate() {
-        if g.id == id {
-            return i;
+    for faction in [Faction::ImmuneSystem, Faction::Infection].iter() {
+        for (i, g) in groups.iter_mut().filter(|g| g.faction == *faction).enumerate() {
+            g.number = i + 1;
         }
     }
-    unreachable!();
+
+    groups
 }
 
 fn get_group_counts(groups: &Vec<Group>) -> (u64, u64) {
@@ -173,94 +503,474 @@ fn is_fight_done(groups: &Vec<Group>) -> bool {
     found_immune_system == 0 || found_infection == 0
 }
 
-fn units_left_after_battle(mut groups: Vec<Group>, boost: u64) -> (u64, u64) {
+fn armies_event(groups: &[Group], round: u64) -> BattleEvent {
+    let mut alive: Vec<&Group> = groups.iter().filter(|g| g.num_units > 0).collect();
+    alive.sort_by_key(|g| g.id);
+    BattleEvent::Armies { round, groups: alive.iter().map(|g| (g.label(), g.num_units)).collect() }
+}
+
+fn units_left_after_battle(mut groups: Vec<Group>, boost: u64, rules: &Rules, events: Option<&mut Vec<BattleEvent>>) -> (u64, u64) {
+    fight(&mut groups, boost, rules, events)
+}
+
+fn fight(groups: &mut Vec<Group>, boost: u64, rules: &Rules, mut events: Option<&mut Vec<BattleEvent>>) -> (u64, u64) {
     for g in groups.iter_mut().filter(|g| g.faction == Faction::ImmuneSystem) {
         g.attack_damage += boost;
     }
-    while !is_fight_done(&groups) {
-        let mut targeted_by: Vec<Option<usize>> = vec![None; groups.len()];
-        let mut target: Vec<Option<usize>> = vec![None; groups.len()];
+
+    // Groups are referred to by their index into `groups`, which is their id
+    // and never changes. Only effective power changes between rounds, so the
+    // damage multipliers and the attack order are worked out up front: for
+    // every group, the multiplier it takes from each of the attack types.
+    let n = groups.len();
+    let mut attack_types: Vec<&str> = groups.iter().map(|g| g.attack_damage_type).collect();
+    attack_types.sort_unstable();
+    attack_types.dedup();
+    let attack_type: Vec<usize> = groups.iter()
+        .map(|g| attack_types.binary_search(&g.attack_damage_type).unwrap())
+        .collect();
+    let multipliers: Vec<Vec<Multiplier>> = groups.iter()
+        .map(|d| attack_types.iter().map(|t| d.damage_multiplier(t, rules)).collect())
+        .collect();
+
+    let mut attack_order: Vec<usize> = (0..n).collect();
+    attack_order.sort_unstable_by(|&a, &b| groups[a].initiative.cmp(&groups[b].initiative).reverse());
+    let mut selection_order: Vec<usize> = (0..n).collect();
+
+    let mut round = 0;
+    while !is_fight_done(groups) {
+        round += 1;
+        if let Some(events) = events.as_mut() {
+            events.push(armies_event(groups, round));
+        }
+        let mut considered = Vec::new();
+        let mut selected = Vec::new();
 
         // ---------------------------------------------------- TARGET SELECTION
-        groups.sort_unstable_by(compare_for_target_selection);
-        let target_selection_order: Vec<usize> = groups.iter().map(|g| g.id).collect();
-
-        for id in target_selection_order.iter() {
-            let attacker = &groups[find_group_by_id(&groups, *id)].clone();
-            let cmp = |a: &Group, b: &Group| {
-                a.damage_dealt_by(&attacker).cmp(&b.damage_dealt_by(&attacker))
-                    .then(a.effective_power().cmp(&b.effective_power()))
-                    .then(a.initiative.cmp(&b.initiative))
-                    .reverse()
+        selection_order.retain(|&i| groups[i].num_units > 0);
+        selection_order.sort_unstable_by(|&a, &b| compare_for_target_selection(&groups[a], &groups[b], rules));
+        let mut target_order = selection_order.clone();
+        if rules.target_order != rules.selection_order {
+            target_order.sort_unstable_by(|&a, &b| compare_by(&groups[a], &groups[b], &rules.target_order));
+        }
+        let (immune_system, infection): (Vec<usize>, Vec<usize>) = target_order.iter()
+            .partition(|&&i| groups[i].faction == Faction::ImmuneSystem);
+
+        let mut targeted = vec![false; n];
+        let mut target: Vec<Option<usize>> = vec![None; n];
+
+        for &a in selection_order.iter() {
+            let attacker = &groups[a];
+            let power = attacker.effective_power();
+            let enemies = match attacker.faction {
+                Faction::ImmuneSystem => &infection,
+                Faction::Infection => &immune_system,
             };
-            groups.sort_unstable_by(cmp);
 
-            for g in groups.iter().filter(|g| g.num_units > 0 && g.faction != attacker.faction) {
-                if g.damage_dealt_by(attacker) == 0 {
+            // Enemies are visited in the tie-break order for targets, so the
+            // first one with the most damage wins.
+            let mut best: Option<(usize, u64)> = None;
+            for &d in enemies.iter() {
+                let damage = multipliers[d][attack_type[a]].apply(power);
+                if damage == 0 || targeted[d] {
                     continue;
                 }
-                if let None = targeted_by[g.id] {
-                    targeted_by[g.id] = Some(attacker.id);
-                    target[attacker.id] = Some(g.id);
-                    break;
-                } else {
+                if events.is_some() {
+                    // The puzzle lists the damage each group would deal by
+                    // army, the infection first, and not in selection order.
+                    let key = (attacker.faction == Faction::ImmuneSystem, a, d);
+                    considered.push((key, BattleEvent::TargetConsidered {
+                        round, attacker: attacker.label(), defender: groups[d].label(), damage,
+                    }));
+                }
+                match best {
+                    Some((_, best_damage)) if best_damage >= damage => {}
+                    _ => best = Some((d, damage)),
+                }
+            }
+
+            if let Some((d, damage)) = best {
+                targeted[d] = true;
+                target[a] = Some(d);
+                if events.is_some() {
+                    selected.push(BattleEvent::TargetSelected {
+                        round, attacker: attacker.label(), defender: groups[d].label(), damage,
+                    });
                 }
             }
         }
 
+        if let Some(events) = events.as_mut() {
+            considered.sort_by_key(|(key, _)| *key);
+            events.extend(considered.into_iter().map(|(_, event)| event));
+            events.append(&mut selected);
+        }
+
         // -------------------------------------------------------- ATTACK PHASE
-        groups.sort_unstable_by(|a, b| a.initiative.cmp(&b.initiative).reverse());
-        let attack_order: Vec<usize> = groups.iter().map(|g| g.id).collect();
         let mut kills = 0;
-        for attacker_id in attack_order.iter() {
-            let attacker = groups[find_group_by_id(&groups, *attacker_id)].clone();
-            if attacker.num_units == 0 {
+        for &a in attack_order.iter() {
+            if groups[a].num_units == 0 {
                 continue;
             }
-            match target[attacker.id] {
-                Some(defender_id) => {
-                    let defender_index = find_group_by_id(&groups, defender_id);
-                    let defender = &mut groups[defender_index];
-                    kills += defender.incur_attack(&attacker);
-                },
-                None => {
-                    continue
-                },
+            if let Some(d) = target[a] {
+                let damage = multipliers[d][attack_type[a]].apply(groups[a].effective_power());
+                let units_killed = groups[d].incur_damage(damage);
+                kills += units_killed;
+                if let Some(events) = events.as_mut() {
+                    events.push(BattleEvent::Attack { round, attacker: groups[a].label(), defender: groups[d].label(), units_killed });
+                }
             }
         }
         if kills == 0 {
             break; // break stalemate
         }
     }
+    if let Some(events) = events {
+        events.push(armies_event(groups, round + 1));
+    }
 
-    get_group_counts(&groups)
+    get_group_counts(groups)
 }
 
-fn main() -> io::Result<()> {
-    let mut input = String::new();
-    io::stdin().lock().read_to_string(&mut input).unwrap();
-    let input = &input[..];
-    let groups = get_groups_from_input(input);
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    ImmuneSystemWins(u64),
+    InfectionWins(u64),
+    Stalemate,
+}
 
-    let (_found_immune_system, found_infection) = units_left_after_battle(groups.clone(), 0);
-    println!("Number of units left after pre-boost battle: {}", found_infection);
+fn battle_outcome(groups: &[Group], boost: u64, rules: &Rules) -> Outcome {
+    match units_left_after_battle(groups.to_vec(), boost, rules, None) {
+        (found_immune_system, 0) => Outcome::ImmuneSystemWins(found_immune_system),
+        (0, found_infection) => Outcome::InfectionWins(found_infection),
+        _ => Outcome::Stalemate,
+    }
+}
+
+// Returns the smallest boost with which the immune system wins, and the
+// number of units it has left. Winning is usually monotonic in the boost, so
+// a binary search finds it, but stalemates can break that: whenever the
+// outcomes seen don't fit the assumption, the result is checked by trying
+// every smaller boost.
+fn find_minimum_boost(groups: &[Group], rules: &Rules) -> Option<(u64, u64)> {
+    // Past this boost every attack that does any damage at all kills a whole
+    // group, so boosting any further can't turn the battle around.
+    let most_hit_points = groups.iter()
+        .filter(|g| g.faction == Faction::Infection)
+        .map(|g| g.num_units * g.hit_points)
+        .max()
+        .unwrap_or(0);
+    let limit = match rules.smallest_damaging_multiplier() {
+        Some(m) => (most_hit_points * m.denominator).div_ceil(m.numerator),
+        None => 0,
+    };
+
+    let mut outcomes: Vec<(u64, Outcome)> = Vec::new();
+    let mut outcome_for = |boost| {
+        let outcome = battle_outcome(groups, boost, rules);
+        outcomes.push((boost, outcome));
+        outcome
+    };
+    let wins = |outcome| matches!(outcome, Outcome::ImmuneSystemWins(_));
+
+    if let Outcome::ImmuneSystemWins(units) = outcome_for(0) {
+        return Some((0, units));
+    }
 
     let mut min_boost = 0;
-    let mut max_boost = 128;
+    let mut max_boost = 1;
+    while !wins(outcome_for(max_boost)) {
+        if max_boost > limit {
+            return None;
+        }
+        min_boost = max_boost;
+        max_boost *= 2;
+    }
 
-    let mut boost;
     while max_boost - min_boost > 1 {
-        boost = (min_boost + max_boost) / 2;
-        let (_found_immune_system, found_infection) = units_left_after_battle(groups.clone(), boost);
-        if found_infection == 0 {
+        let boost = (min_boost + max_boost) / 2;
+        if wins(outcome_for(boost)) {
             max_boost = boost;
         } else {
             min_boost = boost;
         }
     }
 
-    let (found_immune_system, _found_infection) = units_left_after_battle(groups.clone(), max_boost);
-    println!("Number of units left after post-boost battle: {}", found_immune_system);
+    let monotonic = outcomes.iter().all(|&(boost, outcome)| match outcome {
+        Outcome::ImmuneSystemWins(_) => boost >= max_boost,
+        Outcome::InfectionWins(_) => boost < max_boost,
+        Outcome::Stalemate => false,
+    });
+    if !monotonic {
+        for boost in 0..max_boost {
+            if let Outcome::ImmuneSystemWins(units) = battle_outcome(groups, boost, rules) {
+                return Some((boost, units));
+            }
+        }
+    }
+
+    match battle_outcome(groups, max_boost, rules) {
+        Outcome::ImmuneSystemWins(units) => Some((max_boost, units)),
+        _ => unreachable!(),
+    }
+}
+
+fn synthetic_groups(groups_per_army: usize) -> Vec<Group<'static>> {
+    const DAMAGE_TYPES: [&str; 5] = ["bludgeoning", "cold", "fire", "radiation", "slashing"];
+    let mut rng = Rng(0x2018_1224);
+
+    // Initiatives have to be unique, so hand out a shuffled range.
+    let mut initiatives: Vec<u64> = (1..=2 * groups_per_army as u64).collect();
+    for i in (1..initiatives.len()).rev() {
+        let j = rng.range(0, i as i64) as usize;
+        initiatives.swap(i, j);
+    }
+
+    let mut groups = Vec::new();
+    for (i, initiative) in initiatives.into_iter().enumerate() {
+        let faction = if i < groups_per_army { Faction::ImmuneSystem } else { Faction::Infection };
+        let mut weaknesses = Vec::new();
+        let mut immunities = Vec::new();
+        let mut resistances = Vec::new();
+        for &damage_type in DAMAGE_TYPES.iter() {
+            match rng.range(0, 6) {
+                0 => weaknesses.push(damage_type),
+                1 => immunities.push(damage_type),
+                2 => resistances.push(damage_type),
+                _ => {}
+            }
+        }
+
+        let mut modifier_order = Vec::new();
+        for &modifier in [Modifier::Weak, Modifier::Immune, Modifier::Resistant].iter() {
+            let present = match modifier {
+                Modifier::Weak => !weaknesses.is_empty(),
+                Modifier::Immune => !immunities.is_empty(),
+                Modifier::Resistant => !resistances.is_empty(),
+            };
+            if present {
+                let at = rng.range(0, modifier_order.len() as i64) as usize;
+                modifier_order.insert(at, modifier);
+            }
+        }
+        groups.push(Group {
+            id: i,
+            number: i % groups_per_army + 1,
+            faction,
+            num_units: rng.range(1, 10000) as u64,
+            hit_points: rng.range(1, 10000) as u64,
+            weaknesses,
+            immunities,
+            resistances,
+            attack_damage: rng.range(1, 1000) as u64,
+            attack_damage_type: DAMAGE_TYPES[rng.range(0, 4) as usize],
+            initiative,
+            modifier_order,
+        });
+    }
+    groups
+}
+
+fn benchmark() {
+    for &groups_per_army in [10, 100, 1000, 2500].iter() {
+        let groups = synthetic_groups(groups_per_army);
+        let start = std::time::Instant::now();
+        let (found_immune_system, found_infection) = units_left_after_battle(groups, 0, &Rules::default(), None);
+        println!("{} groups per army: battle took {:?}, {} immune system and {} infection units left",
+            groups_per_army, start.elapsed(), found_immune_system, found_infection);
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut log = None;
+    let mut dump = false;
+    let mut rules = Rules::default();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--bench" => {
+                benchmark();
+                return Ok(());
+            },
+            "--log" => log = args.next(),
+            "--dump" => dump = true,
+            "--rules" => {
+                let path = args.next().expect("--rules needs a file name");
+                let text = std::fs::read_to_string(&path)?;
+                rules = match parse_rules(&text) {
+                    Ok(rules) => rules,
+                    Err(e) => {
+                        eprintln!("Invalid rules in {}: {}", path, e);
+                        std::process::exit(1);
+                    }
+                };
+            },
+            _ => {
+                eprintln!("Unknown argument: {}", arg);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let mut input = String::new();
+    io::stdin().lock().read_to_string(&mut input).unwrap();
+    let input = &input[..];
+    let groups = get_groups_from_input(input);
+
+    if let Some(format) = log {
+        let mut events = Vec::new();
+        units_left_after_battle(groups.clone(), 0, &rules, Some(&mut events));
+        print_events(&events, format == "json");
+        println!();
+    }
+
+    let mut survivors = groups.clone();
+    let (_found_immune_system, found_infection) = fight(&mut survivors, 0, &rules, None);
+    println!("Number of units left after pre-boost battle: {}", found_infection);
+    if dump {
+        print!("{}", Armies(&survivors));
+    }
+
+    match find_minimum_boost(&groups, &rules) {
+        Some((boost, found_immune_system)) => {
+            println!("Smallest boost for the immune system to win: {}", boost);
+            println!("Number of units left after post-boost battle: {}", found_immune_system);
+            if dump {
+                let mut survivors = groups.clone();
+                fight(&mut survivors, boost, &rules, None);
+                print!("{}", Armies(&survivors));
+            }
+        },
+        None => println!("No boost lets the immune system win."),
+    }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXAMPLE: &str = "Immune System:\n\
+        17 units each with 5390 hit points (weak to radiation, bludgeoning) with an attack that does 4507 fire damage at initiative 2\n\
+        989 units each with 1274 hit points (immune to fire; weak to bludgeoning, slashing) with an attack that does 25 slashing damage at initiative 3\n\
+        \n\
+        Infection:\n\
+        801 units each with 4706 hit points (weak to radiation) with an attack that does 116 bludgeoning damage at initiative 1\n\
+        4485 units each with 2961 hit points (immune to radiation; weak to fire, cold) with an attack that does 12 slashing damage at initiative 4\n";
+
+    // The first two rounds of the example battle, as the puzzle shows them.
+    const EXAMPLE_ROUNDS: &str = "Immune System:\n\
+        Group 1 contains 17 units\n\
+        Group 2 contains 989 units\n\
+        Infection:\n\
+        Group 1 contains 801 units\n\
+        Group 2 contains 4485 units\n\
+        \n\
+        Infection group 1 would deal defending group 1 185832 damage\n\
+        Infection group 1 would deal defending group 2 185832 damage\n\
+        Infection group 2 would deal defending group 2 107640 damage\n\
+        Immune System group 1 would deal defending group 1 76619 damage\n\
+        Immune System group 1 would deal defending group 2 153238 damage\n\
+        Immune System group 2 would deal defending group 1 24725 damage\n\
+        \n\
+        Infection group 2 attacks defending group 2, killing 84 units\n\
+        Immune System group 2 attacks defending group 1, killing 4 units\n\
+        Immune System group 1 attacks defending group 2, killing 51 units\n\
+        Infection group 1 attacks defending group 1, killing 17 units\n\
+        \n\
+        Immune System:\n\
+        Group 2 contains 905 units\n\
+        Infection:\n\
+        Group 1 contains 797 units\n\
+        Group 2 contains 4434 units\n\
+        \n\
+        Infection group 1 would deal defending group 2 184904 damage\n\
+        Immune System group 2 would deal defending group 1 22625 damage\n\
+        Immune System group 2 would deal defending group 2 22625 damage\n\
+        \n\
+        Immune System group 2 attacks defending group 1, killing 4 units\n\
+        Infection group 1 attacks defending group 2, killing 144 units\n";
+
+    // The puzzle's example pins down how ties in target selection and attack
+    // order are broken.
+    #[test]
+    fn example_battle_log_matches_the_puzzle() {
+        let mut events = Vec::new();
+        units_left_after_battle(get_groups_from_input(EXAMPLE), 0, &Rules::default(), Some(&mut events));
+        let text = events_text(&events);
+        assert!(text.starts_with(EXAMPLE_ROUNDS), "{}", text);
+        assert!(text.ends_with("Immune System:\nNo groups remain.\nInfection:\nGroup 1 contains 782 units\nGroup 2 contains 4434 units\n"), "{}", text);
+    }
+
+    #[test]
+    fn puzzle_input_round_trips() {
+        let input = include_str!("../../data/day24.txt");
+        assert_eq!(Armies(&get_groups_from_input(input)).to_string(), input);
+    }
+
+    #[test]
+    fn synthetic_armies_round_trip() {
+        for groups_per_army in 1..=50 {
+            let groups = synthetic_groups(groups_per_army);
+            let text = Armies(&groups).to_string();
+            assert_eq!(get_groups_from_input(&text), groups, "{} groups per army", groups_per_army);
+        }
+    }
+
+    // After a battle one of the armies has no groups left.
+    #[test]
+    fn survivors_of_a_battle_round_trip() {
+        let groups = get_groups_from_input(include_str!("../../data/day24.txt"));
+        for &boost in [0, 34].iter() {
+            let mut survivors = groups.clone();
+            let (immune_system, infection) = fight(&mut survivors, boost, &Rules::default(), None);
+            let text = Armies(&survivors).to_string();
+            let parsed = get_groups_from_input(&text);
+            assert_eq!(get_group_counts(&parsed), (immune_system, infection), "{}", text);
+            assert_eq!(Armies(&parsed).to_string(), text);
+        }
+    }
+
+    #[test]
+    fn empty_armies_round_trip() {
+        let text = "Immune System:\n\nInfection:\n";
+        assert_eq!(get_groups_from_input(text), vec![]);
+        assert_eq!(Armies(&[]).to_string(), text);
+    }
+
+    #[test]
+    fn modifiers_without_an_order_are_written_too() {
+        let mut group = synthetic_groups(1).remove(0);
+        group.weaknesses = vec!["fire"];
+        group.immunities = vec![];
+        group.resistances = vec!["cold", "slashing"];
+        group.modifier_order = vec![Modifier::Resistant];
+        assert!(group.to_string().contains(" (resistant to cold, slashing; weak to fire) "), "{}", group);
+    }
+
+    #[test]
+    fn no_boost_needed_when_the_immune_system_already_wins() {
+        let input = "Immune System:\n\
+            100 units each with 100 hit points with an attack that does 100 fire damage at initiative 2\n\
+            \n\
+            Infection:\n\
+            1 units each with 10 hit points with an attack that does 1 cold damage at initiative 1\n";
+        let groups = get_groups_from_input(input);
+        assert_eq!(find_minimum_boost(&groups, &Rules::default()), Some((0, 100)));
+    }
+
+    #[test]
+    fn boosts_past_full_damage_count_for_resisted_attacks() {
+        // The infection resists the only attack, so it takes a boost of
+        // 199, about twice its hit points, to kill it in one hit.
+        let input = "Immune System:\n\
+            1 units each with 1000 hit points with an attack that does 1 fire damage at initiative 2\n\
+            \n\
+            Infection:\n\
+            1 units each with 100 hit points (resistant to fire) with an attack that does 1000 cold damage at initiative 1\n";
+        let groups = get_groups_from_input(input);
+        assert_eq!(find_minimum_boost(&groups, &Rules::default()), Some((199, 1)));
+    }
+}
@@ -0,0 +1,4 @@
+pub mod netpbm;
+pub mod ocr;
+pub mod rng;
+pub mod summed_area;
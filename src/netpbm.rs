@@ -0,0 +1,81 @@
+use std::io;
+use std::io::Write;
+
+/// Writes a binary PBM of `width` by `height` pixels, in which pixel (x, y)
+/// is black if `black(x, y)` is true. Rows are packed eight pixels to a
+/// byte, leftmost pixel in the highest bit, and padded to whole bytes.
+pub fn write_pbm(w: &mut impl Write, width: usize, height: usize, black: impl Fn(usize, usize) -> bool) -> io::Result<()> {
+    writeln!(w, "P4\n{} {}", width, height)?;
+    for y in 0..height {
+        let mut row = vec![0u8; width.div_ceil(8)];
+        for x in 0..width {
+            if black(x, y) {
+                row[x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+        w.write_all(&row)?;
+    }
+    Ok(())
+}
+
+/// Writes a binary PGM of `width` by `height` pixels, in which pixel (x, y)
+/// has the grey level `grey(x, y)`, from 0 for black to 255 for white.
+pub fn write_pgm(w: &mut impl Write, width: usize, height: usize, grey: impl Fn(usize, usize) -> u8) -> io::Result<()> {
+    writeln!(w, "P5\n{} {}\n255", width, height)?;
+    for y in 0..height {
+        let row: Vec<u8> = (0..width).map(|x| grey(x, y)).collect();
+        w.write_all(&row)?;
+    }
+    Ok(())
+}
+
+/// Writes a binary PPM of `width` by `height` pixels, in which pixel (x, y)
+/// has the red, green and blue levels `colour(x, y)`, each up to 255.
+pub fn write_ppm(w: &mut impl Write, width: usize, height: usize, colour: impl Fn(usize, usize) -> [u8; 3]) -> io::Result<()> {
+    writeln!(w, "P6\n{} {}\n255", width, height)?;
+    for y in 0..height {
+        for x in 0..width {
+            w.write_all(&colour(x, y))?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pbm_rows_are_padded_to_whole_bytes() {
+        let mut out = Vec::new();
+        write_pbm(&mut out, 10, 2, |x, y| x == y || x == 9).unwrap();
+        let mut expected = b"P4\n10 2\n".to_vec();
+        expected.extend_from_slice(&[0b1000_0000, 0b0100_0000, 0b0100_0000, 0b0100_0000]);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn pgm_has_one_byte_per_pixel() {
+        let mut out = Vec::new();
+        write_pgm(&mut out, 3, 2, |x, y| (10 * y + x) as u8).unwrap();
+        let mut expected = b"P5\n3 2\n255\n".to_vec();
+        expected.extend_from_slice(&[0, 1, 2, 10, 11, 12]);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn ppm_has_three_bytes_per_pixel() {
+        let mut out = Vec::new();
+        write_ppm(&mut out, 2, 1, |x, _| [x as u8, 128, 255]).unwrap();
+        let mut expected = b"P6\n2 1\n255\n".to_vec();
+        expected.extend_from_slice(&[0, 128, 255, 1, 128, 255]);
+        assert_eq!(out, expected);
+    }
+
+    #[test]
+    fn empty_images_have_only_a_header() {
+        let mut out = Vec::new();
+        write_pbm(&mut out, 0, 0, |_, _| true).unwrap();
+        assert_eq!(out, b"P4\n0 0\n".to_vec());
+    }
+}
@@ -0,0 +1,90 @@
+use std::collections::HashSet;
+
+/// The width of a letter in the large font the puzzles draw messages in.
+pub const GLYPH_WIDTH: usize = 6;
+/// The height of a letter in the large font.
+pub const GLYPH_HEIGHT: usize = 10;
+/// The number of empty columns between two letters.
+pub const GLYPH_SPACING: usize = 2;
+
+// The letters of the large font that have turned up in puzzle answers.
+const GLYPHS: [(char, [&str; GLYPH_HEIGHT]); 15] = [
+    ('A', ["..##..", ".#..#.", "#....#", "#....#", "#....#", "######", "#....#", "#....#", "#....#", "#....#"]),
+    ('B', ["#####.", "#....#", "#....#", "#....#", "#####.", "#....#", "#....#", "#....#", "#....#", "#####."]),
+    ('C', [".####.", "#....#", "#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "#....#", ".####."]),
+    ('E', ["######", "#.....", "#.....", "#.....", "#####.", "#.....", "#.....", "#.....", "#.....", "######"]),
+    ('F', ["######", "#.....", "#.....", "#.....", "#####.", "#.....", "#.....", "#.....", "#.....", "#....."]),
+    ('G', [".####.", "#....#", "#.....", "#.....", "#.....", "#..###", "#....#", "#....#", "#...##", ".###.#"]),
+    ('H', ["#....#", "#....#", "#....#", "#....#", "######", "#....#", "#....#", "#....#", "#....#", "#....#"]),
+    ('J', ["...###", "....#.", "....#.", "....#.", "....#.", "....#.", "....#.", "#...#.", "#...#.", ".###.."]),
+    ('K', ["#....#", "#...#.", "#..#..", "#.#...", "##....", "##....", "#.#...", "#..#..", "#...#.", "#....#"]),
+    ('L', ["#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "#.....", "######"]),
+    ('N', ["#....#", "##...#", "##...#", "#.#..#", "#.#..#", "#..#.#", "#..#.#", "#...##", "#...##", "#....#"]),
+    ('P', ["#####.", "#....#", "#....#", "#....#", "#####.", "#.....", "#.....", "#.....", "#.....", "#....."]),
+    ('R', ["#####.", "#....#", "#....#", "#....#", "#####.", "#..#..", "#...#.", "#...#.", "#....#", "#....#"]),
+    ('X', ["#....#", "#....#", ".#..#.", ".#..#.", "..##..", "..##..", ".#..#.", ".#..#.", "#....#", "#....#"]),
+    ('Z', ["######", ".....#", ".....#", "....#.", "...#..", "..#...", ".#....", "#.....", "#.....", "######"]),
+];
+
+/// All letters the font knows, in alphabetical order.
+pub fn known_letters() -> String {
+    GLYPHS.iter().map(|(c, _)| *c).collect()
+}
+
+/// Reads a message from the lit points (x, y) of a single line of text in
+/// the large font. Returns None if the points aren't exactly one letter
+/// high, and '?' for every letter that isn't recognised.
+pub fn read_text(points: &[(i64, i64)]) -> Option<String> {
+    let lit: HashSet<(i64, i64)> = points.iter().copied().collect();
+    let min_x = lit.iter().map(|p| p.0).min()?;
+    let max_x = lit.iter().map(|p| p.0).max()?;
+    let min_y = lit.iter().map(|p| p.1).min()?;
+    let max_y = lit.iter().map(|p| p.1).max()?;
+    if max_y - min_y + 1 != GLYPH_HEIGHT as i64 {
+        return None;
+    }
+
+    let column_is_empty = |x: i64| (min_y..=max_y).all(|y| !lit.contains(&(x, y)));
+
+    let mut text = String::new();
+    let mut x = min_x;
+    while x <= max_x {
+        if column_is_empty(x) {
+            x += 1;
+            continue;
+        }
+        // A letter ends at the first empty column, or where the next one
+        // would start if two letters touch.
+        let mut end = x;
+        while end <= max_x && end - x < GLYPH_WIDTH as i64 && !column_is_empty(end) {
+            end += 1;
+        }
+
+        let rows: Vec<String> = (min_y..=max_y).map(|y| {
+            (x..x + GLYPH_WIDTH as i64).map(|gx| if gx < end && lit.contains(&(gx, y)) { '#' } else { '.' }).collect()
+        }).collect();
+        let letter = GLYPHS.iter().find(|(_, glyph)| glyph.iter().zip(rows.iter()).all(|(g, r)| g == r));
+        text.push(letter.map_or('?', |(c, _)| *c));
+        x = end;
+    }
+    Some(text)
+}
+
+/// The lit points of `text` written in the large font, with the top left
+/// corner of the first letter at (0, 0). Returns None if the font doesn't
+/// have one of the letters.
+pub fn render_text(text: &str) -> Option<Vec<(i64, i64)>> {
+    let mut points = Vec::new();
+    for (i, c) in text.chars().enumerate() {
+        let (_, glyph) = GLYPHS.iter().find(|(g, _)| *g == c)?;
+        let left = (i * (GLYPH_WIDTH + GLYPH_SPACING)) as i64;
+        for (y, row) in glyph.iter().enumerate() {
+            for (x, pixel) in row.chars().enumerate() {
+                if pixel == '#' {
+                    points.push((left + x as i64, y as i64));
+                }
+            }
+        }
+    }
+    Some(points)
+}
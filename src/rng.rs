@@ -0,0 +1,19 @@
+/// A small xorshift generator, good enough to make up test and benchmark
+/// inputs. The same seed always gives the same numbers.
+pub struct Rng(pub u64);
+
+impl Rng {
+    /// The next number in the sequence. The seed must not be zero, or
+    /// every number will be.
+    pub fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// A number from `lo` to `hi`, both included.
+    pub fn range(&mut self, lo: i64, hi: i64) -> i64 {
+        lo + (self.next_u64() % (hi - lo + 1) as u64) as i64
+    }
+}
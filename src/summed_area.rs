@@ -0,0 +1,234 @@
+use std::ops::{Add, Sub};
+
+/// A rectangle of cells with its top left corner at (x, y), and the sum of
+/// the values in it.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Rectangle<T> {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+    pub sum: T,
+}
+
+/// A summed-area table over a grid of cells, for summing any rectangle of
+/// the grid in constant time.
+///
+/// The table has an extra row and column of zeroes at the top and left, so
+/// `table[y * (width + 1) + x]` is the sum of all cells above and left of
+/// (x, y), not including row y and column x.
+pub struct SummedAreaTable<T> {
+    width: usize,
+    height: usize,
+    table: Vec<T>,
+}
+
+impl<T> SummedAreaTable<T>
+where
+    T: Copy + Default + Add<Output = T> + Sub<Output = T>,
+{
+    /// Builds the table for a grid of the given size from the value of
+    /// every cell (x, y).
+    pub fn new<F: Fn(usize, usize) -> T>(width: usize, height: usize, value: F) -> SummedAreaTable<T> {
+        let stride = width + 1;
+        let mut table = vec![T::default(); stride * (height + 1)];
+        for y in 0..height {
+            for x in 0..width {
+                table[(y + 1) * stride + x + 1] =
+                    value(x, y) + table[y * stride + x + 1] + table[(y + 1) * stride + x] - table[y * stride + x];
+            }
+        }
+        SummedAreaTable { width, height, table }
+    }
+
+    /// Builds the table for a grid given as rows. Rows shorter than the
+    /// longest one are padded with `T::default()`.
+    pub fn from_grid(grid: &[Vec<T>]) -> SummedAreaTable<T> {
+        let width = grid.iter().map(|row| row.len()).max().unwrap_or(0);
+        SummedAreaTable::new(width, grid.len(), |x, y| grid[y].get(x).copied().unwrap_or_default())
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    fn at(&self, x: usize, y: usize) -> T {
+        self.table[y * (self.width + 1) + x]
+    }
+
+    /// The sum of the `width` by `height` rectangle with its top left corner
+    /// at (x, y). The rectangle has to fit in the grid.
+    pub fn sum(&self, x: usize, y: usize, width: usize, height: usize) -> T {
+        self.at(x + width, y + height) - self.at(x, y + height) - self.at(x + width, y) + self.at(x, y)
+    }
+
+    /// The sum of all cells from (0, 0) up to and including (x, y).
+    pub fn prefix_sum(&self, x: usize, y: usize) -> T {
+        self.at(x + 1, y + 1)
+    }
+
+    /// All placements of a `width` by `height` rectangle that fit in the
+    /// grid, in reading order.
+    pub fn rectangles(&self, width: usize, height: usize) -> impl Iterator<Item = Rectangle<T>> + '_ {
+        let (xs, ys) = if width == 0 || height == 0 || width > self.width || height > self.height {
+            (0, 0)
+        } else {
+            (self.width - width + 1, self.height - height + 1)
+        };
+        (0..ys).flat_map(move |y| (0..xs).map(move |x| Rectangle { x, y, width, height, sum: self.sum(x, y, width, height) }))
+    }
+}
+
+impl<T> SummedAreaTable<T>
+where
+    T: Copy + Default + Add<Output = T> + Sub<Output = T> + PartialOrd,
+{
+    /// The `side` by `side` square with the largest sum, the first one in
+    /// reading order on ties, or None if the square doesn't fit.
+    pub fn max_square(&self, side: usize) -> Option<Rectangle<T>> {
+        max_by_sum(self.rectangles(side, side))
+    }
+
+    /// The rectangle with the largest sum among all rectangles of at most
+    /// `area` cells.
+    pub fn max_rectangle_with_area_at_most(&self, area: usize) -> Option<Rectangle<T>> {
+        let mut candidates = Vec::new();
+        for height in 1..=self.height.min(area) {
+            for width in 1..=self.width.min(area / height) {
+                candidates.extend(max_by_sum(self.rectangles(width, height)));
+            }
+        }
+        max_by_sum(candidates.into_iter())
+    }
+
+    /// All placements of a `width` by `height` rectangle with a sum greater
+    /// than `threshold`.
+    pub fn rectangles_above(&self, width: usize, height: usize, threshold: T) -> Vec<Rectangle<T>> {
+        self.rectangles(width, height).filter(|r| r.sum > threshold).collect()
+    }
+}
+
+fn max_by_sum<T: PartialOrd>(rectangles: impl Iterator<Item = Rectangle<T>>) -> Option<Rectangle<T>> {
+    let mut best: Option<Rectangle<T>> = None;
+    for r in rectangles {
+        match &best {
+            Some(b) if b.sum >= r.sum => {}
+            _ => best = Some(r),
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rng::Rng;
+
+    fn random_grid(width: usize, height: usize, rng: &mut Rng) -> Vec<Vec<i64>> {
+        (0..height).map(|_| (0..width).map(|_| rng.range(-9, 9)).collect()).collect()
+    }
+
+    fn brute_force_sum(grid: &[Vec<i64>], x: usize, y: usize, width: usize, height: usize) -> i64 {
+        grid[y..y + height].iter().map(|row| row[x..x + width].iter().sum::<i64>()).sum()
+    }
+
+    #[test]
+    fn sums_match_brute_force() {
+        let mut rng = Rng(0x2018_1211);
+        let grid = random_grid(7, 5, &mut rng);
+        let table = SummedAreaTable::from_grid(&grid);
+        for y in 0..5 {
+            for x in 0..7 {
+                for height in 0..=5 - y {
+                    for width in 0..=7 - x {
+                        assert_eq!(table.sum(x, y, width, height), brute_force_sum(&grid, x, y, width, height));
+                    }
+                }
+                assert_eq!(table.prefix_sum(x, y), brute_force_sum(&grid, 0, 0, x + 1, y + 1));
+            }
+        }
+    }
+
+    #[test]
+    fn short_rows_are_padded() {
+        let table = SummedAreaTable::from_grid(&[vec![1, 2, 3], vec![4], vec![]]);
+        assert_eq!((table.width(), table.height()), (3, 3));
+        assert_eq!(table.sum(0, 0, 3, 3), 10);
+        assert_eq!(table.sum(1, 1, 2, 2), 0);
+        assert_eq!(table.sum(0, 1, 1, 1), 4);
+    }
+
+    #[test]
+    fn rectangles_in_reading_order() {
+        let table = SummedAreaTable::from_grid(&[vec![1, 2, 3], vec![4, 5, 6]]);
+        let found: Vec<(usize, usize, i32)> = table.rectangles(2, 1).map(|r| (r.x, r.y, r.sum)).collect();
+        assert_eq!(found, vec![(0, 0, 3), (1, 0, 5), (0, 1, 9), (1, 1, 11)]);
+    }
+
+    #[test]
+    fn empty_and_oversized_rectangles_have_no_placements() {
+        let table = SummedAreaTable::from_grid(&[vec![1, 2, 3], vec![4, 5, 6]]);
+        assert_eq!(table.rectangles(0, 1).count(), 0);
+        assert_eq!(table.rectangles(1, 0).count(), 0);
+        assert_eq!(table.rectangles(4, 1).count(), 0);
+        assert_eq!(table.rectangles(1, 3).count(), 0);
+        assert_eq!(table.max_square(3), None);
+        assert_eq!(table.max_rectangle_with_area_at_most(0), None);
+
+        let empty = SummedAreaTable::<i32>::from_grid(&[]);
+        assert_eq!(empty.max_square(1), None);
+        assert_eq!(empty.max_rectangle_with_area_at_most(4), None);
+    }
+
+    #[test]
+    fn max_square_prefers_the_first_on_ties() {
+        let table = SummedAreaTable::from_grid(&[vec![1, 0, 1], vec![0, 1, 0]]);
+        let square = table.max_square(2).unwrap();
+        assert_eq!((square.x, square.y, square.sum), (0, 0, 2));
+    }
+
+    #[test]
+    fn area_limit_matches_brute_force() {
+        let mut rng = Rng(0x2018_1212);
+        let grid = random_grid(6, 4, &mut rng);
+        let table = SummedAreaTable::from_grid(&grid);
+        for area in 1..=24 {
+            let mut best = None;
+            for height in 1..=4 {
+                for width in 1..=6 {
+                    if width * height > area {
+                        continue;
+                    }
+                    for y in 0..=4 - height {
+                        for x in 0..=6 - width {
+                            let sum = brute_force_sum(&grid, x, y, width, height);
+                            if Some(sum) > best {
+                                best = Some(sum);
+                            }
+                        }
+                    }
+                }
+            }
+            let found = table.max_rectangle_with_area_at_most(area).unwrap();
+            assert!(found.width * found.height <= area);
+            assert_eq!(Some(found.sum), best, "area {}", area);
+            assert_eq!(found.sum, brute_force_sum(&grid, found.x, found.y, found.width, found.height));
+        }
+    }
+
+    #[test]
+    fn threshold_is_exclusive() {
+        let table = SummedAreaTable::from_grid(&[vec![1, 2, 3], vec![4, 5, 6]]);
+        // The columns add up to 5, 7 and 9.
+        let found: Vec<(usize, usize)> = table.rectangles_above(1, 2, 6).iter().map(|r| (r.x, r.y)).collect();
+        assert_eq!(found, vec![(1, 0), (2, 0)]);
+        let found: Vec<(usize, usize)> = table.rectangles_above(1, 2, 7).iter().map(|r| (r.x, r.y)).collect();
+        assert_eq!(found, vec![(2, 0)]);
+        assert!(table.rectangles_above(3, 2, 21).is_empty());
+        assert_eq!(table.rectangles_above(3, 2, 20).len(), 1);
+    }
+}